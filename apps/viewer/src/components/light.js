@@ -17,9 +17,13 @@ function convertHex(hex,alpha){
 }
 
 function getLightProperties(component) {
+    const light = component.el.getAttribute('light') || {};
+    // Parse through three.js to also accept short hex and named colours.
+    const color = new AFRAME.THREE.Color(light.color || '#FFF');
     return {
         position: component.el.object3D.position,
-        intensity: 1.0
+        color: convertHex(color.getHexString(), 255),
+        intensity: light.intensity !== undefined ? light.intensity : 1.0
     };
 }
 
@@ -1,4 +1,6 @@
 window.objectId = window.objectId || 0;
+// Textures have their own ids, each part of a model may have a different one
+window.textureId = window.textureId || 0;
 
 function accessWebtracing(callback) {
     if (window.webtracing) {
@@ -8,6 +10,26 @@ function accessWebtracing(callback) {
     }
 }
 
+function getTextureProperties(material) {
+    const image = material && material.map && material.map.image;
+    if (!image) {
+        return undefined;
+    }
+
+    // Read the pixels back through a canvas
+    const canvas = document.createElement('canvas');
+    canvas.width = image.width;
+    canvas.height = image.height;
+    const ctx = canvas.getContext('2d');
+    ctx.drawImage(image, 0, 0);
+
+    return {
+        width: image.width,
+        height: image.height,
+        data: ctx.getImageData(0, 0, image.width, image.height).data
+    };
+}
+
 function getModelProperties(component) {
     const modelComponents = component.el.components['gltf-model'] || component.el.components['obj-model'];
     const model = modelComponents.model;
@@ -15,12 +37,14 @@ function getModelProperties(component) {
 
         console.log('model', model);
         return model.children
-            .map(m => m.geometry)
-            .filter(g => !!g)
-            .map(geometry => ({
+            .filter(m => !!m.geometry)
+            .map(({ geometry, material }) => ({
                 position: component.el.object3D.position,
                 vertices: geometry.attributes.position.array,
-                triangles: geometry.index ? geometry.index.array : []
+                normals: geometry.attributes.normal ? geometry.attributes.normal.array : [],
+                uvs: geometry.attributes.uv ? geometry.attributes.uv.array : [],
+                triangles: geometry.index ? geometry.index.array : [],
+                texture: getTextureProperties(material)
             }));
     }
     else {
@@ -29,6 +53,20 @@ function getModelProperties(component) {
     }
 }
 
+function getTransformProperties(component) {
+    const { position, quaternion, scale } = component.el.object3D;
+    return {
+        position: { x: position.x, y: position.y, z: position.z },
+        rotation: { x: quaternion.x, y: quaternion.y, z: quaternion.z, w: quaternion.w },
+        scale: { x: scale.x, y: scale.y, z: scale.z }
+    };
+}
+
+function isSameTransform(a, b) {
+    return ['position', 'rotation', 'scale'].every(key =>
+        Object.keys(a[key]).every(axis => a[key][axis] === b[key][axis]));
+}
+
 // Registering component in foo-component.js
 AFRAME.registerComponent('rendered-model', {
     schema: {},
@@ -37,11 +75,19 @@ AFRAME.registerComponent('rendered-model', {
             accessWebtracing(wasm => {
                 try {
                     const models = getModelProperties(this);
-                    for (const model of models) {
-                        wasm.addModel(this.objectId, model);
+                    // Every part of the model is a separate object, with the transform of the entity
+                    this.objectIds = models.map(() => window.objectId++);
+                    this.textureIds = [];
+                    models.forEach((model, i) => {
+                        if (model.texture) {
+                            model.texture.id = window.textureId++;
+                            this.textureIds.push(model.texture.id);
+                        }
+                        wasm.addModel(this.objectIds[i], model);
                         console.log('addModel');
-                    }
+                    });
                     this.isInit = true;
+                    this.sendTransform();
                 }
                 catch (err) {
                     console.log(err);
@@ -50,53 +96,43 @@ AFRAME.registerComponent('rendered-model', {
             });
         }
     },
+    // Moving the model only changes the transform of its objects, the meshes are uploaded once
+    sendTransform: function() {
+        const transform = getTransformProperties(this);
+        this.lastTransform = transform;
+        accessWebtracing(wasm => {
+            for (const objectId of this.objectIds) {
+                wasm.setTransform(objectId, transform);
+            }
+        });
+    },
     init: function () {
-        this.objectId = window.objectId++;
+        this.objectIds = [];
+        this.textureIds = [];
         this.isInit = false;
-
-        const position = this.el.object3D.position;
-        this.lastPosition = { x: position.x, y: position.y, z: position.z };
         this.initModel();
     },
     update: function () {
-        const position = this.el.object3D.position;
         if (this.isInit) {
-            this.lastPosition = { x: position.x, y: position.y, z: position.z };
-            accessWebtracing(wasm => {
-                const models = getModelProperties(this);
-                let i = 0;
-                for (const model of models) {
-                    if(i === 0) wasm.updateModel(this.objectId, model);
-                    else wasm.addModel(this.objectId, model);
-                    i++;
-                    console.log('updateModel');
-                }
-            });
+            this.sendTransform();
         }
     },
     tick: function () {
-        // Update is not called when the color or the position changes. We have to check every tick for this
-        const position = this.el.object3D.position;
-
-        if (this.isInit && (this.lastPosition.x !== position.x
-            || this.lastPosition.y !== position.y
-            || this.lastPosition.z !== position.z)) {
-            accessWebtracing(wasm => {
-                const models = getModelProperties(this);
-                let i = 0;
-                for (const model of models) {
-                    if(i === 0) wasm.updateModel(this.objectId, model);
-                    else wasm.addModel(this.objectId, model);
-                    i++;
-                    console.log('updateModel');
-                }
-            });
-            this.lastPosition = { x: position.x, y: position.y, z: position.z };
+        // Update is not called when the transform changes. We have to check every tick for this
+        if (this.isInit && !isSameTransform(this.lastTransform, getTransformProperties(this))) {
+            this.sendTransform();
         }
     },
     remove: function () {
         if (this.isInit) {
-            accessWebtracing(wasm => wasm.removeModel(this.objectId));
+            accessWebtracing(wasm => {
+                for (const objectId of this.objectIds) {
+                    wasm.removeModel(objectId);
+                }
+                for (const textureId of this.textureIds) {
+                    wasm.removeTexture(textureId);
+                }
+            });
         }
     },
     pause: function () {},
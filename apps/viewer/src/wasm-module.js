@@ -23,8 +23,10 @@ function setLoading(active) {
 class WorkerPool {
     constructor(nbWorkers) {
         this.workers = [];
-        this.queuedJobs = [];
         this.lastWorkerChunkId = 0;
+        // Tiles of the last frame, to display them again with other tone mapping settings.
+        this.lastFrame = undefined;
+        this.frameInProgress = false;
 
         for (let i = 0; i < nbWorkers; i++) {
             this.workers.push(new WebTracingWorker(i, this));
@@ -32,38 +34,36 @@ class WorkerPool {
     }
 
     clear() {
-        this.queuedJobs = [];
-    }
-
-    getIdleWorker() {
         for (const worker of this.workers) {
-            if (!worker.isWorking) {
-                return worker;
-            }
+            worker.queuedJobs = [];
         }
+    }
 
-        return undefined;
+    // Every worker keeps the samples of the tiles it rendered, so a tile always
+    // goes to the same worker for the progressive rendering to refine it.
+    getTileWorker(tileIndex) {
+        return this.workers[tileIndex % this.workers.length];
     }
 
     beginJob(data, ctx) {
-        const worker = this.getIdleWorker();
-        if (worker) {
+        const worker = this.getTileWorker(data.tile_index);
+        if (!worker.isWorking) {
             worker.beginJob(this.lastWorkerChunkId++, data, ctx);
         } else {
-            this.queuedJobs.push({ id: this.lastWorkerChunkId++, data, ctx });
+            worker.queuedJobs.push({ id: this.lastWorkerChunkId++, data, ctx });
         }
     }
 
-    startNextJob() {
-        let worker = this.getIdleWorker();
-        while(worker && this.queuedJobs.length > 0) {
-            const job = this.queuedJobs.shift();
+    startNextJob(worker) {
+        if (worker.queuedJobs.length > 0) {
+            const job = worker.queuedJobs.shift();
             worker.beginJob(job.id, job.data, job.ctx);
-
-            worker = this.getIdleWorker();
+            return;
         }
 
-        if (this.queuedJobs.length === 0) {
+        if (this.frameInProgress && this.workers.every(worker => !worker.isWorking && worker.queuedJobs.length === 0)) {
+            this.frameInProgress = false;
+
             // Frame complete, log the stats
             const perfEntryName = 'frame-#' + frameId;
             performance.measure(perfEntryName, perfEntryName);
@@ -91,6 +91,7 @@ class WebTracingWorker {
         this.worker.onmessage = (e) => this.onMessage(e);
         this.parent = pool;
         this.currentJob = undefined;
+        this.queuedJobs = [];
         this.isWorking = false;
     }
 
@@ -141,13 +142,14 @@ class WebTracingWorker {
         this.isWorking = false;
         this.currentJob = undefined;
         this.ctx = undefined;
-        this.parent.startNextJob();
+        this.parent.startNextJob(this);
     }
 }
 
 const workerPool = new WorkerPool(nbWorkers);
 
-export function draw(ctx, tile_size, width, height) {
+// A progressive draw refines the previous ones until the camera or the scene changes.
+export function draw(ctx, tile_size, width, height, progressive = false) {
     workerPool.clear();
 
     // Stats stuff
@@ -162,11 +164,13 @@ export function draw(ctx, tile_size, width, height) {
         for (let tile_x = 0; tile_x < width; tile_x += tile_size) {
             jobs.push({
                 type: 'draw',
+                tile_index: jobs.length,
                 tile_x,
                 tile_y,
                 tile_size,
                 width,
-                height
+                height,
+                progressive
             });
         }
     }
@@ -183,6 +187,8 @@ export function draw(ctx, tile_size, width, height) {
 
     jobs.sort((a, b) => distanceFromCenter(a) - distanceFromCenter(b));
 
+    workerPool.lastFrame = { jobs, ctx };
+    workerPool.frameInProgress = true;
     for (const job of jobs) {
         workerPool.beginJob(job, ctx);
     }
@@ -196,12 +202,16 @@ export function setCamera(cameraEntity) {
         const position = worldCameraObject.position;
         const rotation = worldCameraObject.rotation.toVector3();
         const fov = camera.fov;
+        // Also updates matrixWorldInverse.
+        camera.updateMatrixWorld();
 
         const message = {
             type: 'set_camera',
             position,
             rotation,
-            fov
+            fov,
+            view: Array.from(camera.matrixWorldInverse.elements),
+            projection: Array.from(camera.projectionMatrix.elements)
         };
 
         workerPool.sendToEveryone(message);
@@ -220,12 +230,62 @@ export function setRenderingSettings(settings) {
     workerPool.sendToEveryone(settings);
 }
 
+// Depth of field: `aperture` is the lens radius, zero for a pinhole camera.
+export function setLens(data) {
+    data.type = 'set_lens';
+    workerPool.sendToEveryone(data);
+}
+
+// `projection` is 'perspective', 'orthographic', 'fisheye' or 'equirectangular'.
+// `orthoHeight` is the height of the orthographic view in world units.
+export function setProjection(data) {
+    data.type = 'set_projection';
+    workerPool.sendToEveryone(data);
+}
+
+// The last frame is displayed again with the new settings, without rendering it again.
+export function setToneMapping(data) {
+    data.type = 'set_tone_mapping';
+    workerPool.sendToEveryone(data);
+
+    const lastFrame = workerPool.lastFrame;
+    if (lastFrame) {
+        for (const job of lastFrame.jobs) {
+            workerPool.beginJob(Object.assign({}, job, { type: 'tone_map' }), lastFrame.ctx);
+        }
+    }
+}
+
 export function createOrEditLight(id, data) {
     data.type = 'create_or_edit_light';
     data.id = id;
     workerPool.sendToEveryone(data);
 }
 
+export function createOrEditSpotLight(id, data) {
+    data.type = 'create_or_edit_spot_light';
+    data.id = id;
+    workerPool.sendToEveryone(data);
+}
+
+export function createOrEditDirectionalLight(id, data) {
+    data.type = 'create_or_edit_directional_light';
+    data.id = id;
+    workerPool.sendToEveryone(data);
+}
+
+export function createOrEditRectLight(id, data) {
+    data.type = 'create_or_edit_rect_light';
+    data.id = id;
+    workerPool.sendToEveryone(data);
+}
+
+export function createOrEditDiskLight(id, data) {
+    data.type = 'create_or_edit_disk_light';
+    data.id = id;
+    workerPool.sendToEveryone(data);
+}
+
 export function removeLight(id) {
     workerPool.sendToEveryone({
         type: 'remove_light',
@@ -284,9 +344,86 @@ export function updateModel(id, data) {
     workerPool.sendToEveryone(data);
 }
 
+export function removeTexture(textureId) {
+    workerPool.sendToEveryone({
+        type: 'remove_texture',
+        texture_id: textureId
+    });
+}
+
+// Move an object with `position`, a `rotation` quaternion and a `scale`, without uploading it again.
+export function setTransform(id, data) {
+    data.type = 'set_transform';
+    data.id = id;
+    workerPool.sendToEveryone(data);
+}
+
 export function removeModel(id) {
     workerPool.sendToEveryone({
         type: 'remove_model',
         id
     });
 }
+
+export function setEnvironmentColor(data) {
+    data.type = 'set_environment_color';
+    workerPool.sendToEveryone(data);
+}
+
+export function setEnvironmentGradient(data) {
+    data.type = 'set_environment_gradient';
+    workerPool.sendToEveryone(data);
+}
+
+// `data.data` holds the bytes of a Radiance .hdr file.
+export function setEnvironmentHdr(data) {
+    data.type = 'set_environment_hdr';
+    workerPool.sendToEveryone(data);
+}
+
+export function setEnvironmentRotation(rotation) {
+    workerPool.sendToEveryone({
+        type: 'set_environment_rotation',
+        rotation
+    });
+}
+
+// The sun is given either by `elevation` and `azimuth` in degrees, or by a `date`
+// ({ month, day, hour }) and a `latitude`. It becomes the light `sunId`.
+export function setSky(sunId, data) {
+    data.type = 'set_sky';
+    data.sunId = sunId;
+    workerPool.sendToEveryone(data);
+}
+
+export function addMesh(meshId, data) {
+    data.type = 'add_mesh';
+    data.mesh_id = meshId;
+    workerPool.sendToEveryone(data);
+}
+
+export function removeMesh(meshId) {
+    workerPool.sendToEveryone({
+        type: 'remove_mesh',
+        mesh_id: meshId
+    });
+}
+
+export function addInstance(id, data) {
+    data.type = 'add_instance';
+    data.id = id;
+    workerPool.sendToEveryone(data);
+}
+
+export function updateInstance(id, data) {
+    data.type = 'update_instance';
+    data.id = id;
+    workerPool.sendToEveryone(data);
+}
+
+export function removeInstance(id) {
+    workerPool.sendToEveryone({
+        type: 'remove_instance',
+        id
+    });
+}
@@ -14,7 +14,9 @@ import('../../wasm-module/pkg').then(wasm => {
             switch (call.type) {
                 case 'draw':
                     const before = performance.now();
-                    const image = renderingContext.draw(
+                    const draw = call.progressive ? renderingContext.draw_progressive : renderingContext.draw;
+                    const image = draw.call(
+                        renderingContext,
                         call.tile_x,
                         call.tile_y,
                         call.tile_size,
@@ -26,10 +28,56 @@ import('../../wasm-module/pkg').then(wasm => {
                     postMessage({ duration: (after - before).toFixed(1) });
                     break;
 
+                case 'tone_map': {
+                    // Same tile as the last draw, with the current tone mapping settings.
+                    const before = performance.now();
+                    const image = renderingContext.tone_map_tile(call.tile_x, call.tile_y, call.tile_size);
+                    const after = performance.now();
+
+                    postMessage(image.buffer, [image.buffer]);
+                    postMessage({ duration: (after - before).toFixed(1) });
+                    break;
+                }
+
                 case 'set_camera':
                     renderingContext.camera_fov = call.fov;
                     renderingContext.camera_pos = wasm.Vector3.new(call.position.x, call.position.y, call.position.z);
                     renderingContext.camera_rotation = wasm.Vector3.new(call.rotation.x, call.rotation.y, call.rotation.z);
+                    if (!call.view || !renderingContext.set_camera_matrices(call.view, call.projection)) {
+                        renderingContext.reset_camera_frame();
+                    }
+                    break;
+
+                case 'set_lens':
+                    renderingContext.camera_aperture = call.aperture;
+                    renderingContext.camera_focus_distance = call.focusDistance;
+                    renderingContext.camera_blades = call.blades || 0;
+                    break;
+
+                case 'set_projection':
+                    renderingContext.camera_projection = {
+                        perspective: wasm.CameraProjection.Perspective,
+                        orthographic: wasm.CameraProjection.Orthographic,
+                        fisheye: wasm.CameraProjection.Fisheye,
+                        equirectangular: wasm.CameraProjection.Equirectangular
+                    }[call.projection];
+                    if (call.orthoHeight) {
+                        renderingContext.camera_ortho_height = call.orthoHeight;
+                    }
+                    break;
+
+                case 'set_tone_mapping':
+                    renderingContext.tone_mapping = {
+                        linear: wasm.ToneMappingOperator.Linear,
+                        reinhard: wasm.ToneMappingOperator.Reinhard,
+                        extendedReinhard: wasm.ToneMappingOperator.ExtendedReinhard,
+                        aces: wasm.ToneMappingOperator.Aces,
+                        agx: wasm.ToneMappingOperator.Agx
+                    }[call.operator];
+                    renderingContext.exposure = call.exposure || 0;
+                    if (call.whitePoint) {
+                        renderingContext.white_point = call.whitePoint;
+                    }
                     break;
 
                 case 'set_rendering_settings':
@@ -37,6 +85,17 @@ import('../../wasm-module/pkg').then(wasm => {
                     {
                         renderingContext.sample_per_pixel = call.sample_per_pixel;
                     }
+                    if (call.filter)
+                    {
+                        renderingContext.filter = {
+                            box: wasm.ReconstructionFilter.Box,
+                            tent: wasm.ReconstructionFilter.Tent,
+                            gaussian: wasm.ReconstructionFilter.Gaussian,
+                            mitchell: wasm.ReconstructionFilter.Mitchell,
+                            blackmanHarris: wasm.ReconstructionFilter.BlackmanHarris
+                        }[call.filter];
+                        renderingContext.filter_radius = call.filter_radius || 0;
+                    }
                     break;
 
                 case 'create_or_edit_light':
@@ -45,9 +104,125 @@ import('../../wasm-module/pkg').then(wasm => {
                         call.position.x,
                         call.position.y,
                         call.position.z,
+                        call.color.r,
+                        call.color.g,
+                        call.color.b,
+                        call.intensity);
+                    break;
+
+                case 'create_or_edit_spot_light':
+                    renderingContext.create_or_edit_spot_light(
+                        call.id,
+                        call.position.x,
+                        call.position.y,
+                        call.position.z,
+                        call.direction.x,
+                        call.direction.y,
+                        call.direction.z,
+                        call.innerAngle,
+                        call.outerAngle,
+                        call.color.r,
+                        call.color.g,
+                        call.color.b,
+                        call.intensity);
+                    break;
+
+                case 'create_or_edit_directional_light':
+                    renderingContext.create_or_edit_directional_light(
+                        call.id,
+                        call.direction.x,
+                        call.direction.y,
+                        call.direction.z,
+                        call.angularDiameter,
+                        call.color.r,
+                        call.color.g,
+                        call.color.b,
+                        call.intensity);
+                    break;
+
+                case 'create_or_edit_rect_light':
+                    renderingContext.create_or_edit_rect_light(
+                        call.id,
+                        call.position.x,
+                        call.position.y,
+                        call.position.z,
+                        call.u.x,
+                        call.u.y,
+                        call.u.z,
+                        call.v.x,
+                        call.v.y,
+                        call.v.z,
+                        call.color.r,
+                        call.color.g,
+                        call.color.b,
+                        call.intensity);
+                    break;
+
+                case 'create_or_edit_disk_light':
+                    renderingContext.create_or_edit_disk_light(
+                        call.id,
+                        call.position.x,
+                        call.position.y,
+                        call.position.z,
+                        call.normal.x,
+                        call.normal.y,
+                        call.normal.z,
+                        call.radius,
+                        call.color.r,
+                        call.color.g,
+                        call.color.b,
+                        call.intensity);
+                    break;
+
+                case 'set_environment_color':
+                    renderingContext.set_environment_color(
+                        call.color.r,
+                        call.color.g,
+                        call.color.b,
                         call.intensity);
                     break;
 
+                case 'set_environment_gradient':
+                    renderingContext.set_environment_gradient(
+                        call.bottom.r,
+                        call.bottom.g,
+                        call.bottom.b,
+                        call.top.r,
+                        call.top.g,
+                        call.top.b);
+                    break;
+
+                case 'set_environment_hdr':
+                    renderingContext.set_environment_hdr(
+                        new Uint8Array(call.data),
+                        call.strength,
+                        call.rotation);
+                    break;
+
+                case 'set_environment_rotation':
+                    renderingContext.set_environment_rotation(call.rotation);
+                    break;
+
+                case 'set_sky':
+                    if (call.date) {
+                        renderingContext.set_sky_at(
+                            call.sunId,
+                            call.date.month,
+                            call.date.day,
+                            call.date.hour,
+                            call.latitude,
+                            call.turbidity,
+                            call.intensity);
+                    } else {
+                        renderingContext.set_sky(
+                            call.sunId,
+                            call.elevation,
+                            call.azimuth,
+                            call.turbidity,
+                            call.intensity);
+                    }
+                    break;
+
                 case 'remove_light':
                     renderingContext.remove_light(call.id);
                     break;
@@ -142,27 +317,81 @@ import('../../wasm-module/pkg').then(wasm => {
                     break;
 
                 case 'add_model':
-                    renderingContext.add_model(
-                        call.id,
-                        call.position.x,
-                        call.position.y,
-                        call.position.z,
-                        call.vertices,
-                        call.triangles);
+                case 'update_model':
+                    if (call.type === 'add_model') {
+                        renderingContext.add_model(
+                            call.id,
+                            call.position.x,
+                            call.position.y,
+                            call.position.z,
+                            call.vertices,
+                            call.normals,
+                            call.uvs,
+                            call.triangles);
+                    } else {
+                        renderingContext.update_model(
+                            call.id,
+                            call.position.x,
+                            call.position.y,
+                            call.position.z,
+                            call.vertices,
+                            call.normals,
+                            call.uvs,
+                            call.triangles);
+                    }
+
+                    if (call.texture) {
+                        renderingContext.add_texture(
+                            call.texture.id,
+                            call.texture.width,
+                            call.texture.height,
+                            call.texture.data,
+                            true);
+                        renderingContext.set_lambert_texture(call.id, call.texture.id, wasm.Wrapping.Repeat);
+                    }
                     break;
 
-                case 'update_model':
-                    renderingContext.update_model(
+                case 'remove_model':
+                    renderingContext.remove_model(call.id);
+                    break;
+
+                case 'remove_texture':
+                    renderingContext.remove_texture(call.texture_id);
+                    break;
+
+                case 'set_transform':
+                    renderingContext.set_transform(
                         call.id,
                         call.position.x,
                         call.position.y,
                         call.position.z,
-                        call.vertices,
-                        call.triangles);
+                        call.rotation.x,
+                        call.rotation.y,
+                        call.rotation.z,
+                        call.rotation.w,
+                        call.scale.x,
+                        call.scale.y,
+                        call.scale.z);
                     break;
 
-                case 'remove_model':
-                    renderingContext.remove_model(call.id);
+                case 'add_mesh':
+                    renderingContext.add_mesh(call.mesh_id, call.vertices, call.normals || [], call.uvs || [], call.triangles || []);
+                    break;
+
+                case 'remove_mesh':
+                    renderingContext.remove_mesh(call.mesh_id);
+                    break;
+
+                case 'add_instance':
+                    renderingContext.add_instance(call.id, call.mesh_id, call.matrix);
+                    break;
+
+                case 'update_instance':
+                    renderingContext.update_instance(call.id, call.matrix);
+                    break;
+
+                case 'remove_instance':
+                    renderingContext.remove_instance(call.id);
                     break;
             }
         } catch(e) {
@@ -0,0 +1,92 @@
+use crate::pathtracer::hit::Hit;
+use crate::pathtracer::material::MaterialTrait;
+use nalgebra_glm::Vec3;
+
+/// Id of the pixels where no surface was hit.
+pub const NO_ID: u32 = u32::MAX;
+
+/// Auxiliary outputs of a pixel, besides its colour, averaged over its samples.
+#[derive(Clone, Copy, Debug)]
+pub struct Aovs {
+    /// Distance from the camera to the first surface hit.
+    pub depth: f32,
+    /// World position of the first surface hit.
+    pub position: Vec3,
+    /// Shading normal of the first surface hit, in world space.
+    pub normal: Vec3,
+    /// Colour of the first surface hit, black where nothing was hit.
+    pub albedo: Vec3,
+    /// Shape of the first surface hit by one of the samples.
+    pub object_id: u32,
+    /// Id of the material of that shape.
+    pub material_id: u32,
+    /// Light reaching the camera straight from the lights, the emissive surfaces or
+    /// the environment, or after one bounce on the first surface hit.
+    pub direct: Vec3,
+    /// Light reaching the camera after more bounces.
+    pub indirect: Vec3,
+    /// Fraction of the samples that hit a surface. The depth, the position and the
+    /// normal are averaged over these samples only, and are zero when there are none.
+    pub coverage: f32,
+}
+
+impl Aovs {
+    /// Outputs of a pixel without any sample.
+    pub fn empty() -> Aovs {
+        Aovs {
+            depth: 0.,
+            position: Vec3::new(0., 0., 0.),
+            normal: Vec3::new(0., 0., 0.),
+            albedo: Vec3::new(0., 0., 0.),
+            object_id: NO_ID,
+            material_id: NO_ID,
+            direct: Vec3::new(0., 0., 0.),
+            indirect: Vec3::new(0., 0., 0.),
+            coverage: 0.,
+        }
+    }
+
+    /// Outputs of a sample whose camera ray first hit a surface after `distance`.
+    /// The lighting is left to the caller.
+    pub fn from_hit(hit: &Hit, distance: f32) -> Aovs {
+        Aovs {
+            depth: distance,
+            position: hit.point,
+            normal: hit.normal.normalize(),
+            albedo: hit.material.albedo(&hit.uv, &hit.point),
+            object_id: hit.object_id,
+            material_id: hit.material_id,
+            coverage: 1.,
+            ..Aovs::empty()
+        }
+    }
+
+    /// Average these outputs, standing for `weight` samples, with `other`
+    /// standing for `other_weight` samples.
+    pub fn merge(&mut self, other: &Aovs, weight: f32, other_weight: f32) {
+        let total = weight + other_weight;
+        if total <= 0. {
+            return;
+        }
+
+        let hits = self.coverage * weight;
+        let other_hits = other.coverage * other_weight;
+        if hits + other_hits > 0. {
+            let (a, b) = (hits / (hits + other_hits), other_hits / (hits + other_hits));
+            self.depth = a * self.depth + b * other.depth;
+            self.position = a * self.position + b * other.position;
+            self.normal = a * self.normal + b * other.normal;
+        }
+        self.coverage = (hits + other_hits) / total;
+
+        let (a, b) = (weight / total, other_weight / total);
+        self.albedo = a * self.albedo + b * other.albedo;
+        self.direct = a * self.direct + b * other.direct;
+        self.indirect = a * self.indirect + b * other.indirect;
+
+        if self.object_id == NO_ID {
+            self.object_id = other.object_id;
+            self.material_id = other.material_id;
+        }
+    }
+}
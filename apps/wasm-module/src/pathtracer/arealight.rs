@@ -0,0 +1,221 @@
+use crate::pathtracer::camera::Ray;
+use crate::pathtracer::light::{LightSample, LightTrait};
+use crate::pathtracer::math::orthonormal_basis;
+use nalgebra_glm::{length2, pi, two_pi, Vec3};
+use rand::rngs::SmallRng;
+use rand::Rng;
+
+/// Light sample on a planar area light, from a point picked uniformly on its surface.
+fn sample_area(point: &Vec3, light_point: Vec3, normal: &Vec3, area: f32, radiance: Vec3) -> Option<LightSample> {
+    let to_light = light_point - point;
+    let distance_squared = length2(&to_light);
+    let distance = distance_squared.sqrt();
+    if distance <= 0. {
+        return None;
+    }
+
+    // The light only emits on the side of its normal.
+    let direction = to_light / distance;
+    let cos_light = -normal.dot(&direction);
+    if cos_light <= 0. {
+        return None;
+    }
+
+    Some(LightSample {
+        direction,
+        distance,
+        radiance,
+        pdf: Some(area_pdf(distance, &direction, normal, area)),
+    })
+}
+
+/// Density of a uniform sample on a planar light of the given area, per unit of solid
+/// angle, for a unit `direction` reaching it at `distance`.
+fn area_pdf(distance: f32, direction: &Vec3, normal: &Vec3, area: f32) -> f32 {
+    distance * distance / (-normal.dot(direction) * area)
+}
+
+/// Distance along a ray to the plane through `origin` with the given normal, when the
+/// ray reaches its emitting side between 0 and `t_max`.
+fn hit_plane(ray: &Ray, origin: &Vec3, normal: &Vec3, t_max: f32) -> Option<f32> {
+    let denominator = normal.dot(&ray.direction);
+    if denominator >= 0. {
+        return None;
+    }
+
+    let t = normal.dot(&(origin - ray.origin)) / denominator;
+    if t <= 0.001 || t >= t_max {
+        return None;
+    }
+
+    Some(t)
+}
+
+/// Rectangle emitting light on the side of its normal, `u × v`.
+pub struct RectLight {
+    id: u32,
+    /// Center of the rectangle.
+    pub position: Vec3,
+    /// Edges of the rectangle.
+    pub u: Vec3,
+    pub v: Vec3,
+    /// Emitted radiance of each RGB channel.
+    pub radiance: Vec3,
+    normal: Vec3,
+    area: f32,
+}
+
+impl RectLight {
+    pub fn new(id: u32, position: Vec3, u: Vec3, v: Vec3, radiance: Vec3) -> RectLight {
+        let cross = u.cross(&v);
+        RectLight {
+            id,
+            position,
+            u,
+            v,
+            radiance,
+            normal: cross.normalize(),
+            area: cross.magnitude(),
+        }
+    }
+
+    fn intersect(&self, ray: &Ray, t_max: f32) -> Option<f32> {
+        if self.area <= 0. {
+            return None;
+        }
+
+        let t = hit_plane(ray, &self.position, &self.normal, t_max)?;
+        let (s, r) = self.local_coordinates(&ray.point_at_parameter(t));
+        if s.abs() > 0.5 || r.abs() > 0.5 {
+            return None;
+        }
+
+        Some(t)
+    }
+
+    /// Coordinates of a point of the plane along the edges, in [-0.5, 0.5] inside the rectangle.
+    fn local_coordinates(&self, point: &Vec3) -> (f32, f32) {
+        let offset = point - self.position;
+        let uu = self.u.dot(&self.u);
+        let uv = self.u.dot(&self.v);
+        let vv = self.v.dot(&self.v);
+        let pu = offset.dot(&self.u);
+        let pv = offset.dot(&self.v);
+        let determinant = uu * vv - uv * uv;
+        (
+            (pu * vv - pv * uv) / determinant,
+            (pv * uu - pu * uv) / determinant,
+        )
+    }
+}
+
+impl LightTrait for RectLight {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn sample(&self, point: &Vec3, rng: &mut SmallRng) -> Option<LightSample> {
+        if self.area <= 0. {
+            return None;
+        }
+
+        let s: f32 = rng.gen_range(-0.5, 0.5);
+        let t: f32 = rng.gen_range(-0.5, 0.5);
+        let light_point = self.position + s * self.u + t * self.v;
+        sample_area(point, light_point, &self.normal, self.area, self.radiance)
+    }
+
+    fn pdf(&self, point: &Vec3, direction: &Vec3) -> f32 {
+        let ray = Ray {
+            origin: *point,
+            direction: *direction,
+        };
+        match self.intersect(&ray, f32::MAX) {
+            Some(distance) => area_pdf(distance, direction, &self.normal, self.area),
+            None => 0.,
+        }
+    }
+
+    fn hit(&self, ray: &Ray, t_max: f32) -> Option<Vec3> {
+        self.intersect(ray, t_max).map(|_| self.radiance)
+    }
+}
+
+/// Disk emitting light on the side of its normal.
+pub struct DiskLight {
+    id: u32,
+    pub position: Vec3,
+    /// Unit vector perpendicular to the disk, zero when the disk was given no direction.
+    pub normal: Vec3,
+    pub radius: f32,
+    /// Emitted radiance of each RGB channel.
+    pub radiance: Vec3,
+}
+
+impl DiskLight {
+    /// A zero normal gives a disk that emits nothing, like a zero radius.
+    pub fn new(id: u32, position: Vec3, normal: Vec3, radius: f32, radiance: Vec3) -> DiskLight {
+        let length = normal.magnitude();
+        DiskLight {
+            id,
+            position,
+            normal: if length > 0. { normal / length } else { Vec3::new(0., 0., 0.) },
+            radius,
+            radiance,
+        }
+    }
+
+    fn area(&self) -> f32 {
+        pi::<f32>() * self.radius * self.radius
+    }
+
+    fn is_degenerate(&self) -> bool {
+        self.radius <= 0. || self.normal == Vec3::new(0., 0., 0.)
+    }
+
+    fn intersect(&self, ray: &Ray, t_max: f32) -> Option<f32> {
+        if self.is_degenerate() {
+            return None;
+        }
+
+        let t = hit_plane(ray, &self.position, &self.normal, t_max)?;
+        if length2(&(ray.point_at_parameter(t) - self.position)) > self.radius * self.radius {
+            return None;
+        }
+
+        Some(t)
+    }
+}
+
+impl LightTrait for DiskLight {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn sample(&self, point: &Vec3, rng: &mut SmallRng) -> Option<LightSample> {
+        if self.is_degenerate() {
+            return None;
+        }
+
+        let (tangent, bitangent) = orthonormal_basis(&self.normal);
+        let r = self.radius * rng.gen_range(0., 1f32).sqrt();
+        let phi = rng.gen_range(0., two_pi::<f32>());
+        let light_point = self.position + r * (phi.cos() * tangent + phi.sin() * bitangent);
+        sample_area(point, light_point, &self.normal, self.area(), self.radiance)
+    }
+
+    fn pdf(&self, point: &Vec3, direction: &Vec3) -> f32 {
+        let ray = Ray {
+            origin: *point,
+            direction: *direction,
+        };
+        match self.intersect(&ray, f32::MAX) {
+            Some(distance) => area_pdf(distance, direction, &self.normal, self.area()),
+            None => 0.,
+        }
+    }
+
+    fn hit(&self, ray: &Ray, t_max: f32) -> Option<Vec3> {
+        self.intersect(ray, t_max).map(|_| self.radiance)
+    }
+}
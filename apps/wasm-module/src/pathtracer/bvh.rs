@@ -0,0 +1,712 @@
+use crate::pathtracer::camera::Ray;
+use nalgebra_glm::Vec3;
+
+/// Number of buckets used to evaluate the surface area heuristic along an axis.
+const SAH_BINS: usize = 12;
+/// Relative cost of traversing a node compared to intersecting a primitive.
+const TRAVERSAL_COST: f32 = 1.0;
+/// Above this number of primitives, a leaf is always split.
+const MAX_LEAF_SIZE: usize = 8;
+/// Nodes the traversal stack holds without allocating. Deeper trees spill to the heap.
+const STACK_SIZE: usize = 64;
+/// A refitted tree is rebuilt once its SAH cost grows past this factor of its cost when built.
+const REBUILD_THRESHOLD: f32 = 1.5;
+/// Parent of the root node.
+const NO_PARENT: usize = usize::MAX;
+
+/// Result of a ray intersection that the BVH can compare by distance.
+pub trait Intersection {
+    fn distance(&self) -> f32;
+}
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// A box containing nothing, the identity of `union`.
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Vec3::new(f32::MIN, f32::MIN, f32::MIN),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Vec3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Vec3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn grow(&self, point: &Vec3) -> Aabb {
+        self.union(&Aabb::new(*point, *point))
+    }
+
+    /// Enlarge the box on every side, to keep flat boxes (axis-aligned triangles) hittable.
+    pub fn pad(&self, margin: f32) -> Aabb {
+        let margin = Vec3::new(margin, margin, margin);
+        Aabb {
+            min: self.min - margin,
+            max: self.max + margin,
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+
+        let extent = self.max - self.min;
+        2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+    }
+
+    /// Slab test. Returns the distance at which the ray enters the box.
+    pub fn hit(&self, ray: &Ray, inv_direction: &Vec3, t_min: f32, t_max: f32) -> Option<f32> {
+        let mut t_enter = t_min;
+        let mut t_exit = t_max;
+
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - ray.origin[axis]) * inv_direction[axis];
+            let t1 = (self.max[axis] - ray.origin[axis]) * inv_direction[axis];
+            let (t0, t1) = if t0 > t1 { (t1, t0) } else { (t0, t1) };
+
+            t_enter = if t0 > t_enter { t0 } else { t_enter };
+            t_exit = if t1 < t_exit { t1 } else { t_exit };
+
+            if t_exit < t_enter {
+                return None;
+            }
+        }
+
+        Some(t_enter)
+    }
+}
+
+/// A node of the flattened tree.
+///
+/// For a leaf, `count` primitives start at `first` in `Bvh::indices`.
+/// For an interior node, `count` is zero and the children are at `first` and `first + 1`.
+#[derive(Clone, Debug)]
+struct BvhNode {
+    bounds: Aabb,
+    first: usize,
+    count: usize,
+}
+
+impl BvhNode {
+    fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+/// Bounding volume hierarchy built with the surface area heuristic.
+///
+/// The tree only knows about the bounding boxes of the primitives: it stores
+/// indices into the caller's list and asks the caller to intersect them.
+///
+/// When primitives move, the tree can be refitted instead of rebuilt: the topology
+/// is kept and only the bounds of the affected nodes are updated.
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+    /// Parent of each node, to walk back up the tree when refitting.
+    parents: Vec<usize>,
+    /// Leaf containing each primitive.
+    leaves: Vec<usize>,
+    /// SAH cost of the tree right after the build.
+    build_cost: f32,
+}
+
+impl Default for Bvh {
+    fn default() -> Self {
+        Bvh::new()
+    }
+}
+
+impl Bvh {
+    pub fn new() -> Bvh {
+        Bvh {
+            nodes: Vec::new(),
+            indices: Vec::new(),
+            parents: Vec::new(),
+            leaves: Vec::new(),
+            build_cost: 0.0,
+        }
+    }
+
+    /// Build the tree over the given primitive bounds.
+    pub fn build(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * bounds.len()),
+            indices: (0..bounds.len()).collect(),
+            parents: Vec::with_capacity(2 * bounds.len()),
+            leaves: vec![0; bounds.len()],
+            build_cost: 0.0,
+        };
+
+        if bounds.is_empty() {
+            return bvh;
+        }
+
+        let centroids: Vec<Vec3> = bounds.iter().map(|b| b.centroid()).collect();
+
+        bvh.nodes.push(BvhNode {
+            bounds: Aabb::empty(),
+            first: 0,
+            count: bounds.len(),
+        });
+        bvh.parents.push(NO_PARENT);
+        bvh.subdivide(0, bounds, &centroids);
+
+        for (node_index, node) in bvh.nodes.iter().enumerate() {
+            if node.is_leaf() {
+                for &i in &bvh.indices[node.first..node.first + node.count] {
+                    bvh.leaves[i] = node_index;
+                }
+            }
+        }
+        bvh.build_cost = bvh.cost();
+
+        bvh
+    }
+
+    /// Update the bounds of the nodes containing the given primitives.
+    ///
+    /// `bounds` gives the new bounds of a primitive, by its index in the order used to build the tree.
+    pub fn refit<F: Fn(usize) -> Aabb>(&mut self, bounds: F, primitives: &[usize]) {
+        for &primitive in primitives {
+            if primitive >= self.leaves.len() {
+                continue;
+            }
+
+            let leaf = self.leaves[primitive];
+            let first = self.nodes[leaf].first;
+            let count = self.nodes[leaf].count;
+            self.nodes[leaf].bounds = self.indices[first..first + count]
+                .iter()
+                .fold(Aabb::empty(), |acc, &i| acc.union(&bounds(i)));
+
+            // Propagate to the root.
+            let mut node_index = self.parents[leaf];
+            while node_index != NO_PARENT {
+                let left = self.nodes[node_index].first;
+                let new_bounds = self.nodes[left].bounds.union(&self.nodes[left + 1].bounds);
+                if new_bounds == self.nodes[node_index].bounds {
+                    break;
+                }
+                self.nodes[node_index].bounds = new_bounds;
+                node_index = self.parents[node_index];
+            }
+        }
+    }
+
+    /// SAH cost of the whole tree, relative to the area of the root.
+    pub fn cost(&self) -> f32 {
+        let root_area = self.bounds().surface_area();
+        if root_area <= 0.0 {
+            return 0.0;
+        }
+
+        let cost: f32 = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let area = node.bounds.surface_area();
+                if node.is_leaf() {
+                    node.count as f32 * area
+                } else {
+                    TRAVERSAL_COST * area
+                }
+            })
+            .sum();
+
+        cost / root_area
+    }
+
+    /// True when refits made the tree so loose that a rebuild would pay for itself.
+    pub fn is_degraded(&self) -> bool {
+        self.cost() > self.build_cost * REBUILD_THRESHOLD
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        match self.nodes.first() {
+            Some(root) => root.bounds,
+            None => Aabb::empty(),
+        }
+    }
+
+    fn subdivide(&mut self, node_index: usize, bounds: &[Aabb], centroids: &[Vec3]) {
+        let first = self.nodes[node_index].first;
+        let count = self.nodes[node_index].count;
+
+        // Compute the bounds of the node and of its centroids.
+        let mut node_bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for &i in &self.indices[first..first + count] {
+            node_bounds = node_bounds.union(&bounds[i]);
+            centroid_bounds = centroid_bounds.grow(&centroids[i]);
+        }
+        self.nodes[node_index].bounds = node_bounds;
+
+        if count <= 1 {
+            return;
+        }
+
+        // Find the best split with binned SAH.
+        let split = self.find_split(first, count, &node_bounds, &centroid_bounds, bounds, centroids);
+        let (axis, split_position) = match split {
+            Some(split) => split,
+            None => {
+                if count <= MAX_LEAF_SIZE {
+                    return;
+                }
+                // No split is worth it but the leaf is too big: split at the median.
+                let axis = largest_axis(&centroid_bounds);
+                if centroid_bounds.max[axis] - centroid_bounds.min[axis] <= 0.0 {
+                    return;
+                }
+                (axis, centroid_bounds.centroid()[axis])
+            }
+        };
+
+        // Partition the indices around the split.
+        let mut i = first;
+        let mut j = first + count;
+        while i < j {
+            if centroids[self.indices[i]][axis] < split_position {
+                i += 1;
+            } else {
+                j -= 1;
+                self.indices.swap(i, j);
+            }
+        }
+
+        let left_count = i - first;
+        if left_count == 0 || left_count == count {
+            return;
+        }
+
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode {
+            bounds: Aabb::empty(),
+            first,
+            count: left_count,
+        });
+        self.nodes.push(BvhNode {
+            bounds: Aabb::empty(),
+            first: i,
+            count: count - left_count,
+        });
+        self.nodes[node_index].first = left;
+        self.nodes[node_index].count = 0;
+        self.parents.push(node_index);
+        self.parents.push(node_index);
+
+        self.subdivide(left, bounds, centroids);
+        self.subdivide(left + 1, bounds, centroids);
+    }
+
+    /// Returns the axis and position of the cheapest split, if it beats keeping a leaf.
+    fn find_split(
+        &self,
+        first: usize,
+        count: usize,
+        node_bounds: &Aabb,
+        centroid_bounds: &Aabb,
+        bounds: &[Aabb],
+        centroids: &[Vec3],
+    ) -> Option<(usize, f32)> {
+        let leaf_cost = count as f32;
+        let parent_area = node_bounds.surface_area();
+        if parent_area <= 0.0 {
+            return None;
+        }
+
+        let mut best: Option<(usize, f32)> = None;
+        let mut best_cost = leaf_cost;
+
+        let axes = centroid_bounds.min.iter().zip(centroid_bounds.max.iter()).enumerate();
+        for (axis, (&min, &max)) in axes {
+            if max - min <= 0.0 {
+                continue;
+            }
+
+            // Fill the bins.
+            let mut bin_bounds = [Aabb::empty(); SAH_BINS];
+            let mut bin_counts = [0usize; SAH_BINS];
+            let scale = SAH_BINS as f32 / (max - min);
+            for &i in &self.indices[first..first + count] {
+                let bin = (((centroids[i][axis] - min) * scale) as usize).min(SAH_BINS - 1);
+                bin_counts[bin] += 1;
+                bin_bounds[bin] = bin_bounds[bin].union(&bounds[i]);
+            }
+
+            // Sweep from the right to get the cost of every right side.
+            let mut right_area = [0.0f32; SAH_BINS];
+            let mut right_count = [0usize; SAH_BINS];
+            let mut accumulated = Aabb::empty();
+            let mut accumulated_count = 0;
+            for bin in (1..SAH_BINS).rev() {
+                accumulated = accumulated.union(&bin_bounds[bin]);
+                accumulated_count += bin_counts[bin];
+                right_area[bin] = accumulated.surface_area();
+                right_count[bin] = accumulated_count;
+            }
+
+            // Then sweep from the left and evaluate each split plane.
+            let mut accumulated = Aabb::empty();
+            let mut accumulated_count = 0;
+            for bin in 0..SAH_BINS - 1 {
+                accumulated = accumulated.union(&bin_bounds[bin]);
+                accumulated_count += bin_counts[bin];
+
+                let cost = TRAVERSAL_COST
+                    + (accumulated_count as f32 * accumulated.surface_area()
+                        + right_count[bin + 1] as f32 * right_area[bin + 1])
+                        / parent_area;
+
+                if cost < best_cost {
+                    best_cost = cost;
+                    best = Some((axis, min + (bin + 1) as f32 / scale));
+                }
+            }
+        }
+
+        best
+    }
+
+    /// Find the closest hit. `hit_primitive` is called with the index of a primitive
+    /// and the current closest distance.
+    pub fn hit<H, F>(&self, ray: &Ray, t_min: f32, t_max: f32, mut hit_primitive: F) -> Option<H>
+    where
+        H: Intersection,
+        F: FnMut(usize, f32) -> Option<H>,
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inv_direction = inverse_direction(ray);
+        let mut closest_so_far = t_max;
+        let mut closest_hit: Option<H> = None;
+
+        let mut stack = TraversalStack::new();
+
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+
+            if node.bounds.hit(ray, &inv_direction, t_min, closest_so_far).is_none() {
+                continue;
+            }
+
+            if node.is_leaf() {
+                for &i in &self.indices[node.first..node.first + node.count] {
+                    if let Some(hit) = hit_primitive(i, closest_so_far) {
+                        closest_so_far = hit.distance();
+                        closest_hit = Some(hit);
+                    }
+                }
+            } else {
+                // Visit the nearest child first so the far one is often culled.
+                let left = &self.nodes[node.first];
+                let right = &self.nodes[node.first + 1];
+                let t_left = left.bounds.hit(ray, &inv_direction, t_min, closest_so_far);
+                let t_right = right.bounds.hit(ray, &inv_direction, t_min, closest_so_far);
+
+                match (t_left, t_right) {
+                    (Some(t_left), Some(t_right)) => {
+                        let (near, far) = if t_left <= t_right {
+                            (node.first, node.first + 1)
+                        } else {
+                            (node.first + 1, node.first)
+                        };
+                        stack.push(far);
+                        stack.push(near);
+                    }
+                    (Some(_), None) => stack.push(node.first),
+                    (None, Some(_)) => stack.push(node.first + 1),
+                    (None, None) => (),
+                }
+            }
+        }
+
+        closest_hit
+    }
+
+    /// Returns true as soon as any primitive is hit. Used for shadow rays.
+    pub fn any_hit<F>(&self, ray: &Ray, t_min: f32, t_max: f32, mut hit_primitive: F) -> bool
+    where
+        F: FnMut(usize) -> bool,
+    {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let inv_direction = inverse_direction(ray);
+        let mut stack = TraversalStack::new();
+
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+
+            if node.bounds.hit(ray, &inv_direction, t_min, t_max).is_none() {
+                continue;
+            }
+
+            if node.is_leaf() {
+                for &i in &self.indices[node.first..node.first + node.count] {
+                    if hit_primitive(i) {
+                        return true;
+                    }
+                }
+            } else {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+            }
+        }
+
+        false
+    }
+}
+
+/// Stack of the nodes left to visit, starting with the root.
+struct TraversalStack {
+    nodes: [usize; STACK_SIZE],
+    len: usize,
+    overflow: Vec<usize>,
+}
+
+impl TraversalStack {
+    fn new() -> TraversalStack {
+        TraversalStack {
+            nodes: [0; STACK_SIZE],
+            len: 1,
+            overflow: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, node: usize) {
+        if self.len < STACK_SIZE {
+            self.nodes[self.len] = node;
+            self.len += 1;
+        } else {
+            self.overflow.push(node);
+        }
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        if let Some(node) = self.overflow.pop() {
+            return Some(node);
+        }
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(self.nodes[self.len])
+    }
+}
+
+fn inverse_direction(ray: &Ray) -> Vec3 {
+    Vec3::new(
+        1.0 / ray.direction.x,
+        1.0 / ray.direction.y,
+        1.0 / ray.direction.z,
+    )
+}
+
+fn largest_axis(aabb: &Aabb) -> usize {
+    let extent = aabb.max - aabb.min;
+    if extent.x > extent.y && extent.x > extent.z {
+        0
+    } else if extent.y > extent.z {
+        1
+    } else {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct BoxHit {
+        primitive: usize,
+        t: f32,
+    }
+
+    impl Intersection for BoxHit {
+        fn distance(&self) -> f32 {
+            self.t
+        }
+    }
+
+    /// Unit cube with its lower corner at `(x, y, z)`.
+    fn unit_box(x: f32, y: f32, z: f32) -> Aabb {
+        Aabb::new(Vec3::new(x, y, z), Vec3::new(x + 1., y + 1., z + 1.))
+    }
+
+    /// Cubes on a 10x10x10 grid, with gaps between them.
+    fn grid() -> Vec<Aabb> {
+        let mut boxes = Vec::new();
+        for x in 0..10 {
+            for y in 0..10 {
+                for z in 0..10 {
+                    boxes.push(unit_box(2. * x as f32, 2. * y as f32, 2. * z as f32));
+                }
+            }
+        }
+        boxes
+    }
+
+    fn closest_hit(bvh: &Bvh, boxes: &[Aabb], ray: &Ray) -> Option<BoxHit> {
+        let inv_direction = inverse_direction(ray);
+        bvh.hit(ray, 0., f32::MAX, |i, closest_so_far| {
+            boxes[i]
+                .hit(ray, &inv_direction, 0., closest_so_far)
+                .map(|t| BoxHit { primitive: i, t })
+        })
+    }
+
+    #[test]
+    fn builds_a_tree_around_every_primitive() {
+        let boxes = grid();
+        let bvh = Bvh::build(&boxes);
+
+        assert_eq!(bvh.bounds(), Aabb::new(Vec3::new(0., 0., 0.), Vec3::new(19., 19., 19.)));
+        assert!(bvh.node_count() > 1);
+        assert!(bvh.nodes.iter().all(|node| !node.is_leaf() || node.count <= MAX_LEAF_SIZE));
+
+        let mut indices = bvh.indices.clone();
+        indices.sort_unstable();
+        assert_eq!(indices, (0..boxes.len()).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn finds_the_closest_primitive() {
+        let boxes = grid();
+        let bvh = Bvh::build(&boxes);
+
+        // Along the Z axis, through the column of cubes at x = 4, y = 6.
+        let ray = Ray {
+            origin: Vec3::new(4.5, 6.5, 30.),
+            direction: Vec3::new(0., 0., -1.),
+        };
+        let hit = closest_hit(&bvh, &boxes, &ray).unwrap();
+        assert_eq!(boxes[hit.primitive], unit_box(4., 6., 18.));
+        assert_eq!(hit.t, 11.);
+
+        // Between the columns.
+        let ray = Ray {
+            origin: Vec3::new(5.5, 6.5, 30.),
+            direction: Vec3::new(0., 0., -1.),
+        };
+        assert!(closest_hit(&bvh, &boxes, &ray).is_none());
+    }
+
+    #[test]
+    fn finds_any_primitive_before_the_maximum_distance() {
+        let boxes = grid();
+        let bvh = Bvh::build(&boxes);
+        let ray = Ray {
+            origin: Vec3::new(4.5, 6.5, 30.),
+            direction: Vec3::new(0., 0., -1.),
+        };
+        let inv_direction = inverse_direction(&ray);
+        let hits = |t_max| bvh.any_hit(&ray, 0., t_max, |i| boxes[i].hit(&ray, &inv_direction, 0., t_max).is_some());
+
+        assert!(hits(12.));
+        assert!(!hits(10.));
+    }
+
+    #[test]
+    fn empty_trees_hit_nothing() {
+        let bvh = Bvh::build(&[]);
+        let ray = Ray {
+            origin: Vec3::new(0., 0., 0.),
+            direction: Vec3::new(0., 0., -1.),
+        };
+
+        assert_eq!(bvh.node_count(), 0);
+        assert!(bvh.bounds().is_empty());
+        assert!(closest_hit(&bvh, &[], &ray).is_none());
+        assert!(!bvh.any_hit(&ray, 0., f32::MAX, |_| true));
+    }
+
+    #[test]
+    fn refits_the_nodes_of_moved_primitives() {
+        let mut boxes = grid();
+        let mut bvh = Bvh::build(&boxes);
+
+        boxes[123] = unit_box(30., 4., 4.);
+        bvh.refit(|i| boxes[i], &[123]);
+        assert_eq!(bvh.bounds(), Aabb::new(Vec3::new(0., 0., 0.), Vec3::new(31., 19., 19.)));
+
+        let ray = Ray {
+            origin: Vec3::new(30.5, 4.5, 30.),
+            direction: Vec3::new(0., 0., -1.),
+        };
+        let hit = closest_hit(&bvh, &boxes, &ray).unwrap();
+        assert_eq!(hit.primitive, 123);
+        assert_eq!(hit.t, 25.);
+    }
+
+    #[test]
+    fn refitted_trees_degrade_when_primitives_scatter() {
+        let mut boxes = grid();
+        let mut bvh = Bvh::build(&boxes);
+
+        // Small moves keep the tree about as good as when it was built.
+        for aabb in boxes.iter_mut().step_by(7) {
+            *aabb = unit_box(aabb.min.x + 0.1, aabb.min.y, aabb.min.z);
+        }
+        let moved: Vec<usize> = (0..boxes.len()).step_by(7).collect();
+        bvh.refit(|i| boxes[i], &moved);
+        assert!(!bvh.is_degraded());
+
+        // Shuffling the cubes stretches the nodes across the grid.
+        let boxes: Vec<Aabb> = (0..boxes.len()).map(|i| boxes[i * 389 % boxes.len()]).collect();
+        let all: Vec<usize> = (0..boxes.len()).collect();
+        bvh.refit(|i| boxes[i], &all);
+        assert!(bvh.is_degraded());
+    }
+
+    #[test]
+    fn traversal_stack_spills_to_the_heap() {
+        let mut stack = TraversalStack::new();
+        for node in 1..3 * STACK_SIZE {
+            stack.push(node);
+        }
+
+        for node in (0..3 * STACK_SIZE).rev() {
+            assert_eq!(stack.pop(), Some(node));
+        }
+        assert_eq!(stack.pop(), None);
+    }
+}
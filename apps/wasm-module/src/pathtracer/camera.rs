@@ -1,16 +1,54 @@
-use nalgebra_glm::{inverse, look_at, pi, rotate_vec3, vec4_to_vec3, Mat4, Vec3, Vec4};
+use crate::pathtracer::triangle::sample_triangle;
+use nalgebra_glm::{
+    inverse, look_at, pi, quat_to_mat4, rotate_vec3, translation, two_pi, vec4_to_vec3, Mat4, Quat, Vec2, Vec3, Vec4,
+};
+use rand::rngs::SmallRng;
+use rand::Rng;
+
+/// How the directions around the camera are mapped to the image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,
+    /// Parallel rays along the view direction, covering `height` world units vertically.
+    Orthographic { height: f32 },
+    /// Equidistant fisheye: the angle from the view direction grows linearly with the
+    /// distance from the image center, up to half the field of view on the image circle.
+    Fisheye,
+    /// Every direction, the longitude along the image width and the latitude along its height.
+    Equirectangular,
+}
 
+#[derive(PartialEq)]
 pub struct Camera {
     pub origin: Vec3,
     pub lower_left_corner: Vec3,
     pub horizontal: Vec3,
     pub vertical: Vec3,
 
+    /// Unit vectors of the camera frame.
+    pub right: Vec3,
+    pub up: Vec3,
+    pub front: Vec3,
+
+    /// Radius of the lens. Zero for a pinhole camera.
+    pub aperture: f32,
+    /// Distance along the view direction of the plane in focus.
+    pub focus_distance: f32,
+    /// Number of diaphragm blades, giving polygonal bokeh. Below three, the lens is round.
+    pub blades: u32,
+
+    /// Field of view in radians, used by the fisheye projection.
+    pub fov: f32,
+    /// The lens only applies to the perspective projection.
+    pub projection: Projection,
+
     pub width: u32,
     pub height: u32,
 }
 
 impl Camera {
+    /// Camera at `camera_pos` looking along -Z, turned by the Euler angles `camera_rotation`
+    /// applied around X, then Y, then Z. The field of view is vertical, in degrees.
     pub fn new(
         camera_pos: Vec3,
         camera_rotation: Vec3,
@@ -25,23 +63,39 @@ impl Camera {
         let axis_y = Vec3::new(0.0, 1.0, 0.0);
         let axis_z = Vec3::new(0.0, 0.0, 1.0);
 
-        let deg_to_radians = pi::<f32>() / 180.0;
         camera_front = rotate_vec3(&camera_front, camera_rotation.x, &axis_x);
         camera_front = rotate_vec3(&camera_front, camera_rotation.y, &axis_y);
         camera_front = rotate_vec3(&camera_front, camera_rotation.z, &axis_z);
         camera_front = camera_front.normalize();
 
-        let camera_center = camera_pos + camera_front;
-        let camera_right = camera_front.cross(&camera_up).normalize();
-        let camera_up = camera_right.cross(&camera_front).normalize();
+        Camera::look_at(camera_pos, camera_pos + camera_front, camera_up, camera_fov, width, height)
+    }
 
-        let aspect = (width as f32) / (height as f32);
-        let camera_fov = camera_fov * deg_to_radians;
-        let projection_matrix = Mat4::new_perspective(aspect, camera_fov, 0.1, 10000.0);
-        let view_matrix = look_at(&camera_pos, &camera_center, &camera_up);
+    /// Camera at `eye` looking at `target`, rolled so that `up` points up in the image.
+    /// The field of view is vertical, in degrees.
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3, fov: f32, width: u32, height: u32) -> Camera {
+        let view_matrix = look_at(&eye, &target, &up);
+        Camera::from_matrices(view_matrix, perspective_matrix(fov, width, height), width, height)
+    }
 
-        let mat = projection_matrix * view_matrix;
-        let inverse_view_proj = inverse(&mat);
+    /// Camera at `position` turned by `rotation` from the default orientation of Three.js,
+    /// looking along -Z with +Y up. The field of view is vertical, in degrees.
+    pub fn from_quaternion(position: Vec3, rotation: Quat, fov: f32, width: u32, height: u32) -> Camera {
+        let camera_to_world = translation(&position) * quat_to_mat4(&rotation.normalize());
+        Camera::from_matrices(inverse(&camera_to_world), perspective_matrix(fov, width, height), width, height)
+    }
+
+    /// Camera from a world to camera matrix and an OpenGL projection matrix, such as the
+    /// `matrixWorldInverse` and `projectionMatrix` of a Three.js camera.
+    /// An orthographic projection matrix gives an orthographic camera.
+    pub fn from_matrices(view_matrix: Mat4, projection_matrix: Mat4, width: u32, height: u32) -> Camera {
+        let camera_to_world = inverse(&view_matrix);
+        let origin = vec4_to_vec3(&camera_to_world.column(3).into_owned());
+        let right = vec4_to_vec3(&camera_to_world.column(0).into_owned()).normalize();
+        let up = vec4_to_vec3(&camera_to_world.column(1).into_owned()).normalize();
+        let front = -vec4_to_vec3(&camera_to_world.column(2).into_owned()).normalize();
+
+        let inverse_view_proj = inverse(&(projection_matrix * view_matrix));
 
         // Compute image corners.
         let plane_lower_left = inverse_view_proj * Vec4::new(-1.0, -1.0, 0.0, 1.0);
@@ -56,25 +110,121 @@ impl Camera {
             vec4_to_vec3(&(plane_lower_right / plane_lower_right.w)) - lower_left_corner;
         let vertical = vec4_to_vec3(&(plane_upper_left / plane_upper_left.w)) - lower_left_corner;
 
+        // The last row of an orthographic projection doesn't depend on the depth.
+        let (fov, projection) = if projection_matrix[(3, 2)] == 0. {
+            (0., Projection::Orthographic { height: 2. / projection_matrix[(1, 1)] })
+        } else {
+            (2. * (1. / projection_matrix[(1, 1)]).atan(), Projection::Perspective)
+        };
+
         Camera {
-            origin: camera_pos,
+            origin,
             lower_left_corner,
             horizontal,
             vertical,
 
+            right,
+            up,
+            front,
+
+            aperture: 0.,
+            focus_distance: 1.,
+            blades: 0,
+
+            fov,
+            projection,
+
             width,
             height,
         }
     }
 
-    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
+    /// Ray through the point `(u, v)` of the image, from its lower left corner.
+    /// Points outside of the image circle of a fisheye don't have any ray.
+    pub fn get_ray(&self, u: f32, v: f32, rng: &mut SmallRng) -> Option<Ray> {
+        let aspect = self.width as f32 / self.height as f32;
+
+        match self.projection {
+            Projection::Perspective => Some(self.perspective_ray(u, v, rng)),
+            Projection::Orthographic { height } => Some(Ray {
+                origin: self.origin + ((u - 0.5) * height * aspect) * self.right + ((v - 0.5) * height) * self.up,
+                direction: self.front,
+            }),
+            Projection::Fisheye => {
+                // Coordinates in [-1, 1] along the smallest side of the image.
+                let scale = self.width.min(self.height) as f32;
+                let x = (2. * u - 1.) * self.width as f32 / scale;
+                let y = (2. * v - 1.) * self.height as f32 / scale;
+                let radius = (x * x + y * y).sqrt();
+                if radius > 1. {
+                    return None;
+                }
+
+                let theta = 0.5 * radius * self.fov;
+                let phi = y.atan2(x);
+                Some(Ray {
+                    origin: self.origin,
+                    direction: theta.sin() * (phi.cos() * self.right + phi.sin() * self.up) + theta.cos() * self.front,
+                })
+            }
+            Projection::Equirectangular => {
+                let longitude = two_pi::<f32>() * (u - 0.5);
+                let latitude = pi::<f32>() * (v - 0.5);
+                Some(Ray {
+                    origin: self.origin,
+                    direction: latitude.cos() * (longitude.sin() * self.right + longitude.cos() * self.front)
+                        + latitude.sin() * self.up,
+                })
+            }
+        }
+    }
+
+    fn perspective_ray(&self, u: f32, v: f32, rng: &mut SmallRng) -> Ray {
         let direction =
-            self.lower_left_corner + (u * self.horizontal) + (v * self.vertical) - self.origin;
+            (self.lower_left_corner + (u * self.horizontal) + (v * self.vertical) - self.origin).normalize();
+
+        if self.aperture <= 0. || self.focus_distance <= 0. {
+            return Ray {
+                origin: self.origin,
+                direction,
+            };
+        }
+
+        // All the rays through the lens meet again on the plane in focus.
+        let focus_point = self.origin + direction * (self.focus_distance / direction.dot(&self.front));
+        let lens = self.aperture * self.sample_lens(rng);
+        let origin = self.origin + lens.x * self.right + lens.y * self.up;
         Ray {
-            origin: self.origin,
-            direction: direction.normalize(),
+            origin,
+            direction: (focus_point - origin).normalize(),
         }
     }
+
+    /// Point picked uniformly on the unit disk, or on the regular polygon inscribed
+    /// in it when the diaphragm has blades.
+    fn sample_lens(&self, rng: &mut SmallRng) -> Vec2 {
+        if self.blades < 3 {
+            let radius = rng.gen_range(0., 1f32).sqrt();
+            let angle = rng.gen_range(0., two_pi::<f32>());
+            return radius * Vec2::new(angle.cos(), angle.sin());
+        }
+
+        // The polygon is made of as many identical triangles around its center.
+        let sector = rng.gen_range(0, self.blades);
+        let step = two_pi::<f32>() / self.blades as f32;
+        let corner = |i: u32| {
+            let angle = i as f32 * step;
+            Vec2::new(-angle.sin(), angle.cos())
+        };
+        let (u, v) = sample_triangle(rng);
+        u * corner(sector) + v * corner(sector + 1)
+    }
+}
+
+/// Perspective projection with a vertical field of view in degrees.
+fn perspective_matrix(fov: f32, width: u32, height: u32) -> Mat4 {
+    let aspect = (width as f32) / (height as f32);
+    Mat4::new_perspective(aspect, fov * pi::<f32>() / 180.0, 0.1, 10000.0)
 }
 
 pub struct Ray {
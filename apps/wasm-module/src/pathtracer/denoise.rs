@@ -0,0 +1,118 @@
+use nalgebra_glm::Vec3;
+
+/// B3 spline, the 1D kernel of the À-Trous wavelet transform.
+const KERNEL: [f32; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+/// Most passes run by the filter, whose last pass then reaches pixels 2^16 pixels away,
+/// farther than any image.
+pub const MAX_ITERATIONS: u32 = 16;
+
+/// Edge-avoiding À-Trous wavelet filter (Dammertz et al. 2010), guided by the
+/// albedo and normal outputs so that the edges and the textures stay sharp.
+pub struct Denoiser {
+    /// Number of passes, the last one reaching pixels `2^iterations` pixels away.
+    /// At most `MAX_ITERATIONS`.
+    pub iterations: u32,
+    /// Tolerance to colour differences, on colours brought to [0, 1] with `x / (1 + x)`.
+    /// Halved at every pass, as the noise gets lower.
+    pub color_sigma: f32,
+    pub normal_sigma: f32,
+    pub albedo_sigma: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            iterations: 4,
+            color_sigma: 0.4,
+            normal_sigma: 0.3,
+            albedo_sigma: 0.1,
+        }
+    }
+}
+
+impl Denoiser {
+    /// Filter an image given row by row, with the albedo and the normal of each pixel.
+    /// The lighting is filtered apart from the albedo, which is multiplied back at the end.
+    pub fn denoise(&self, width: u32, height: u32, color: &[Vec3], albedo: &[Vec3], normal: &[Vec3]) -> Vec<Vec3> {
+        let (width, height) = (width as usize, height as usize);
+        let size = width * height;
+        if color.len() != size || albedo.len() != size || normal.len() != size {
+            return color.to_vec();
+        }
+
+        let mut irradiance: Vec<Vec3> = color.iter()
+            .zip(albedo)
+            .map(|(color, albedo)| demodulate(color, albedo))
+            .collect();
+        let mut filtered = vec![Vec3::new(0., 0., 0.); size];
+
+        for iteration in 0..self.iterations.min(MAX_ITERATIONS) {
+            let step = 1 << iteration;
+            let color_sigma = self.color_sigma / (1 << iteration) as f32;
+            let color_scale = 1. / (color_sigma * color_sigma).max(1e-8);
+            let normal_scale = 1. / (self.normal_sigma * self.normal_sigma).max(1e-8);
+            let albedo_scale = 1. / (self.albedo_sigma * self.albedo_sigma).max(1e-8);
+
+            for y in 0..height {
+                for x in 0..width {
+                    let center = y * width + x;
+                    let center_color = compress(&irradiance[center]);
+
+                    let mut sum = Vec3::new(0., 0., 0.);
+                    let mut total = 0.;
+                    for (j, ky) in KERNEL.iter().enumerate() {
+                        let sy = y as isize + (j as isize - 2) * step;
+                        if sy < 0 || sy >= height as isize {
+                            continue;
+                        }
+                        for (i, kx) in KERNEL.iter().enumerate() {
+                            let sx = x as isize + (i as isize - 2) * step;
+                            if sx < 0 || sx >= width as isize {
+                                continue;
+                            }
+
+                            let other = sy as usize * width + sx as usize;
+                            let color_distance = (compress(&irradiance[other]) - center_color).magnitude_squared();
+                            let normal_distance = (normal[other] - normal[center]).magnitude_squared();
+                            let albedo_distance = (albedo[other] - albedo[center]).magnitude_squared();
+                            let weight = kx * ky * (-color_distance * color_scale
+                                - normal_distance * normal_scale
+                                - albedo_distance * albedo_scale).exp();
+
+                            sum += weight * irradiance[other];
+                            total += weight;
+                        }
+                    }
+
+                    // The center always has a weight of at least 9/64.
+                    filtered[center] = sum / total;
+                }
+            }
+
+            std::mem::swap(&mut irradiance, &mut filtered);
+        }
+
+        irradiance.iter()
+            .zip(albedo)
+            .map(|(irradiance, albedo)| irradiance.component_mul(&modulation(albedo)))
+            .collect()
+    }
+}
+
+/// Albedo divided out of the colours, black albedos being left alone.
+fn modulation(albedo: &Vec3) -> Vec3 {
+    albedo.map(|channel| if channel > 0.01 { channel } else { 1. })
+}
+
+fn demodulate(color: &Vec3, albedo: &Vec3) -> Vec3 {
+    color.component_div(&modulation(albedo))
+}
+
+/// Colour brought to [0, 1], so that the bright fireflies don't stand out in the distances.
+fn compress(color: &Vec3) -> Vec3 {
+    color.map(|channel| {
+        let channel = channel.max(0.);
+        channel / (1. + channel)
+    })
+}
@@ -0,0 +1,99 @@
+use crate::pathtracer::camera::Ray;
+use crate::pathtracer::light::{LightSample, LightTrait};
+use crate::pathtracer::math::sample_cone;
+use nalgebra_glm::{two_pi, Vec3};
+use rand::rngs::SmallRng;
+
+/// Light coming from a very distant source, like the sun.
+///
+/// With an angular diameter, the source is a disk in the sky: it casts soft shadows
+/// and can be seen in reflections.
+pub struct DirectionalLight {
+    id: u32,
+    /// Unit vector the light travels along.
+    pub direction: Vec3,
+    /// Irradiance of each RGB channel on a surface facing the light.
+    pub irradiance: Vec3,
+    /// Cosine of the angular radius of the source.
+    cos_radius: f32,
+}
+
+impl DirectionalLight {
+    /// `angular_diameter` is the apparent size of the source, in radians.
+    /// A zero direction gives a light that emits nothing.
+    pub fn new(id: u32, direction: Vec3, irradiance: Vec3, angular_diameter: f32) -> DirectionalLight {
+        let length = direction.magnitude();
+        DirectionalLight {
+            id,
+            direction: if length > 0. { direction / length } else { Vec3::new(0., 0., 0.) },
+            irradiance,
+            cos_radius: (0.5 * angular_diameter.max(0.)).cos(),
+        }
+    }
+
+    fn solid_angle(&self) -> f32 {
+        two_pi::<f32>() * (1. - self.cos_radius)
+    }
+
+    fn is_degenerate(&self) -> bool {
+        self.direction == Vec3::new(0., 0., 0.)
+    }
+
+    fn is_delta(&self) -> bool {
+        self.cos_radius >= 1.
+    }
+
+    /// Radiance of the disk of the source, uniform over its solid angle.
+    fn radiance(&self) -> Vec3 {
+        self.irradiance / self.solid_angle()
+    }
+}
+
+impl LightTrait for DirectionalLight {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn sample(&self, _point: &Vec3, rng: &mut SmallRng) -> Option<LightSample> {
+        if self.is_degenerate() {
+            return None;
+        }
+
+        if self.is_delta() {
+            return Some(LightSample {
+                direction: -self.direction,
+                distance: f32::MAX,
+                radiance: self.irradiance,
+                pdf: None,
+            });
+        }
+
+        Some(LightSample {
+            direction: sample_cone(&-self.direction, self.cos_radius, rng),
+            distance: f32::MAX,
+            radiance: self.radiance(),
+            pdf: Some(1. / self.solid_angle()),
+        })
+    }
+
+    fn pdf(&self, _point: &Vec3, direction: &Vec3) -> f32 {
+        if self.is_degenerate() || self.is_delta() || -self.direction.dot(direction) < self.cos_radius {
+            return 0.;
+        }
+
+        1. / self.solid_angle()
+    }
+
+    /// Only the rays leaving the scene see the source.
+    fn hit(&self, ray: &Ray, t_max: f32) -> Option<Vec3> {
+        if self.is_degenerate() || self.is_delta() || t_max < f32::MAX {
+            return None;
+        }
+
+        if -self.direction.dot(&ray.direction.normalize()) < self.cos_radius {
+            return None;
+        }
+
+        Some(self.radiance())
+    }
+}
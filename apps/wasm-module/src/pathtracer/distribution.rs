@@ -0,0 +1,191 @@
+/// Piecewise constant density over [0, 1], proportional to a list of weights.
+pub struct Distribution1D {
+    weights: Vec<f32>,
+    /// Cumulative weights, normalized so that the last one is one.
+    cdf: Vec<f32>,
+    /// Sum of the weights divided by their count.
+    integral: f32,
+}
+
+impl Distribution1D {
+    /// Negative weights count as zero. When all the weights are zero,
+    /// the distribution is uniform. There must be at least one weight.
+    pub fn new(weights: Vec<f32>) -> Distribution1D {
+        let weights: Vec<f32> = weights.into_iter().map(|weight| weight.max(0.)).collect();
+        let count = weights.len() as f32;
+
+        let mut cdf = Vec::with_capacity(weights.len() + 1);
+        cdf.push(0.);
+        for (i, weight) in weights.iter().enumerate() {
+            cdf.push(cdf[i] + weight / count);
+        }
+
+        let integral = cdf[weights.len()];
+        for (i, value) in cdf.iter_mut().enumerate() {
+            *value = if integral > 0. {
+                *value / integral
+            } else {
+                i as f32 / count
+            };
+        }
+
+        Distribution1D {
+            weights,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Map a uniform number in [0, 1[ to the distribution.
+    /// Returns the position in [0, 1[, its density and the index of its bucket.
+    pub fn sample(&self, random: f32) -> (f32, f32, usize) {
+        // Last bucket starting at or below the random number, which skips the empty buckets.
+        let index = (self.cdf.partition_point(|&value| value <= random).max(1) - 1).min(self.count() - 1);
+
+        let start = self.cdf[index];
+        let width = self.cdf[index + 1] - start;
+        let offset = if width > 0. {
+            (random - start) / width
+        } else {
+            0.
+        };
+
+        let position = ((index as f32 + offset) / self.count() as f32).min(1. - f32::EPSILON);
+        (position, self.pdf(index), index)
+    }
+
+    /// Density of the bucket `index`.
+    pub fn pdf(&self, index: usize) -> f32 {
+        if self.integral > 0. {
+            self.weights[index] / self.integral
+        } else {
+            1.
+        }
+    }
+}
+
+/// Piecewise constant density over [0, 1]², proportional to a grid of weights
+/// stored row by row.
+pub struct Distribution2D {
+    /// Distribution along each row.
+    rows: Vec<Distribution1D>,
+    /// Distribution of the rows.
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(weights: &[f32], width: usize, height: usize) -> Distribution2D {
+        let rows: Vec<Distribution1D> = weights
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral()).collect());
+
+        Distribution2D { rows, marginal }
+    }
+
+    /// Map two uniform numbers in [0, 1[ to the distribution.
+    /// Returns the position `(x, y)` in [0, 1[² and its density.
+    pub fn sample(&self, random_x: f32, random_y: f32) -> (f32, f32, f32) {
+        let (y, pdf_y, row) = self.marginal.sample(random_y);
+        let (x, pdf_x, _) = self.rows[row].sample(random_x);
+        (x, y, pdf_x * pdf_y)
+    }
+
+    /// Density at the position `(x, y)` in [0, 1]².
+    pub fn pdf(&self, x: f32, y: f32) -> f32 {
+        let row = ((y * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+        let column = ((x * self.rows[row].count() as f32) as usize).min(self.rows[row].count() - 1);
+        self.marginal.pdf(row) * self.rows[row].pdf(column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn densities_follow_the_weights() {
+        let distribution = Distribution1D::new(vec![1., 3., 0., 4.]);
+
+        assert_eq!(distribution.count(), 4);
+        assert_close(distribution.integral(), 2.);
+        assert_close(distribution.pdf(0), 0.5);
+        assert_close(distribution.pdf(1), 1.5);
+        assert_close(distribution.pdf(2), 0.);
+        assert_close(distribution.pdf(3), 2.);
+    }
+
+    #[test]
+    fn samples_are_spread_inside_their_bucket() {
+        let distribution = Distribution1D::new(vec![1., 3., 0., 4.]);
+
+        // The second bucket covers the random numbers from 1/8 to 1/2.
+        let (position, pdf, index) = distribution.sample(0.3125);
+        assert_eq!(index, 1);
+        assert_close(position, 0.375);
+        assert_close(pdf, 1.5);
+
+        let (position, _, index) = distribution.sample(0.);
+        assert_eq!((position, index), (0., 0));
+        let (position, _, index) = distribution.sample(1. - f32::EPSILON);
+        assert_eq!(index, 3);
+        assert!(position < 1.);
+    }
+
+    #[test]
+    fn samples_skip_empty_buckets() {
+        let distribution = Distribution1D::new(vec![0., 1., 0., 0., 1., 0.]);
+
+        for &random in &[0., 0.25, 0.5, 0.75, 1. - f32::EPSILON] {
+            let (_, pdf, index) = distribution.sample(random);
+            assert!(index == 1 || index == 4, "{} gave the empty bucket {}", random, index);
+            assert!(pdf > 0.);
+        }
+    }
+
+    #[test]
+    fn zero_weights_give_a_uniform_distribution() {
+        let distribution = Distribution1D::new(vec![0., -1., 0., 0.]);
+
+        assert_eq!(distribution.integral(), 0.);
+        let (position, pdf, index) = distribution.sample(0.6);
+        assert_eq!(index, 2);
+        assert_close(position, 0.6);
+        assert_eq!(pdf, 1.);
+    }
+
+    #[test]
+    fn grid_densities_match_their_samples() {
+        let weights = [1., 2., 0., 0., 5., 0.];
+        let distribution = Distribution2D::new(&weights, 3, 2);
+
+        // The density integrates to one over the unit square.
+        let total: f32 = (0..6).map(|i| distribution.pdf((i % 3) as f32 / 3. + 0.1, (i / 3) as f32 / 2. + 0.1)).sum();
+        assert_close(total / 6., 1.);
+
+        for &(random_x, random_y) in &[(0.1, 0.1), (0.5, 0.3), (0.9, 0.9), (0.3, 0.7)] {
+            let (x, y, pdf) = distribution.sample(random_x, random_y);
+            assert!(pdf > 0.);
+            assert_close(distribution.pdf(x, y), pdf);
+        }
+
+        // The second row only has weight in its middle cell.
+        let (x, y, pdf) = distribution.sample(0.9, 0.9);
+        assert!((1. / 3.0..2. / 3.).contains(&x) && y >= 0.5);
+        assert_close(pdf, 6. * 5. / 8.);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::pathtracer::distribution::Distribution2D;
+use crate::pathtracer::hdr::HdrImage;
+use crate::pathtracer::light::LightSample;
+use crate::pathtracer::random_unit_vector;
+use crate::pathtracer::sky::SkyEnvironment;
+use crate::pathtracer::sphere::sphere_uv;
+use enum_dispatch::enum_dispatch;
+use nalgebra_glm::{pi, two_pi, Vec3};
+use rand::rngs::SmallRng;
+use rand::Rng;
+use std::rc::Rc;
+
+/// Light arriving from infinitely far away, seen by the rays leaving the scene.
+#[enum_dispatch(Environment)]
+pub trait EnvironmentTrait {
+    /// Radiance arriving from the unit vector `direction`.
+    fn radiance(&self, direction: &Vec3) -> Vec3;
+
+    /// Pick a direction to sample the environment as a light.
+    fn sample(&self, rng: &mut SmallRng) -> Option<LightSample> {
+        let direction = random_unit_vector(rng);
+        Some(LightSample {
+            direction,
+            distance: f32::MAX,
+            radiance: self.radiance(&direction),
+            pdf: Some(1. / (4. * pi::<f32>())),
+        })
+    }
+
+    /// Density of `sample` picking the unit vector `direction`, per unit of solid angle.
+    fn pdf(&self, _direction: &Vec3) -> f32 {
+        1. / (4. * pi::<f32>())
+    }
+
+    /// No light comes from a black environment, so the direct lighting doesn't sample it.
+    fn is_black(&self) -> bool {
+        false
+    }
+}
+
+#[enum_dispatch]
+#[derive(Clone)]
+pub enum Environment {
+    ConstantEnvironment,
+    GradientEnvironment,
+    ImageEnvironment,
+    SkyEnvironment,
+}
+
+/// The same radiance in every direction.
+#[derive(Clone)]
+pub struct ConstantEnvironment {
+    pub color: Vec3,
+}
+
+impl EnvironmentTrait for ConstantEnvironment {
+    fn radiance(&self, _direction: &Vec3) -> Vec3 {
+        self.color
+    }
+
+    fn sample(&self, rng: &mut SmallRng) -> Option<LightSample> {
+        if self.is_black() {
+            return None;
+        }
+
+        let direction = random_unit_vector(rng);
+        Some(LightSample {
+            direction,
+            distance: f32::MAX,
+            radiance: self.color,
+            pdf: Some(self.pdf(&direction)),
+        })
+    }
+
+    fn is_black(&self) -> bool {
+        self.color == Vec3::new(0., 0., 0.)
+    }
+}
+
+/// Vertical blend from `bottom`, straight down, to `top`, straight up.
+#[derive(Clone)]
+pub struct GradientEnvironment {
+    pub bottom: Vec3,
+    pub top: Vec3,
+}
+
+impl Default for GradientEnvironment {
+    /// A white to light blue sky.
+    fn default() -> Self {
+        GradientEnvironment {
+            bottom: Vec3::new(1., 1., 1.),
+            top: Vec3::new(0.5, 0.7, 1.),
+        }
+    }
+}
+
+impl EnvironmentTrait for GradientEnvironment {
+    fn radiance(&self, direction: &Vec3) -> Vec3 {
+        let t = 0.5 * (direction.y + 1.);
+        (1. - t) * self.bottom + t * self.top
+    }
+
+    fn is_black(&self) -> bool {
+        self.bottom == Vec3::new(0., 0., 0.) && self.top == Vec3::new(0., 0., 0.)
+    }
+}
+
+/// An equirectangular HDR image, importance sampled by the luminance of its pixels.
+#[derive(Clone)]
+pub struct ImageEnvironment {
+    image: Rc<HdrImage>,
+    distribution: Rc<Distribution2D>,
+    /// Factor applied to the pixels.
+    pub strength: f32,
+    /// Rotation around the Y axis, in radians.
+    pub rotation: f32,
+}
+
+impl ImageEnvironment {
+    pub fn new(image: Rc<HdrImage>, strength: f32, rotation: f32) -> ImageEnvironment {
+        let width = image.width();
+        let height = image.height();
+
+        // The rows near the poles cover a smaller solid angle.
+        let weights: Vec<f32> = (0..height)
+            .flat_map(|y| {
+                let image = &image;
+                let elevation = pi::<f32>() * (0.5 - (y as f32 + 0.5) / height as f32);
+                (0..width).map(move |x| luminance(&image.pixel(x, y)) * elevation.cos())
+            })
+            .collect();
+        let distribution = Distribution2D::new(&weights, width as usize, height as usize);
+
+        ImageEnvironment {
+            image,
+            distribution: Rc::new(distribution),
+            strength,
+            rotation,
+        }
+    }
+
+    /// Position in the image, in [0, 1]² from the top left corner, of a unit vector
+    /// given in the space of the image.
+    fn image_coordinates(direction: &Vec3) -> (f32, f32) {
+        let uv = sphere_uv(direction);
+        (uv.x, uv.y)
+    }
+
+    fn pixel(&self, x: f32, y: f32) -> Vec3 {
+        let column = ((x * self.image.width() as f32) as u32).min(self.image.width() - 1);
+        let row = ((y * self.image.height() as f32) as u32).min(self.image.height() - 1);
+        self.strength * self.image.pixel(column, row)
+    }
+}
+
+impl EnvironmentTrait for ImageEnvironment {
+    fn radiance(&self, direction: &Vec3) -> Vec3 {
+        let (x, y) = ImageEnvironment::image_coordinates(&rotate_y(direction, -self.rotation));
+        self.pixel(x, y)
+    }
+
+    fn sample(&self, rng: &mut SmallRng) -> Option<LightSample> {
+        let (x, y, pdf) = self.distribution.sample(rng.gen_range(0., 1.), rng.gen_range(0., 1.));
+
+        // Inverse of `sphere_uv`.
+        let phi = two_pi::<f32>() * (x - 0.5);
+        let elevation = pi::<f32>() * (0.5 - y);
+        let cos_elevation = elevation.cos();
+        if pdf <= 0. || cos_elevation <= 0. {
+            return None;
+        }
+
+        let direction = Vec3::new(-cos_elevation * phi.cos(), elevation.sin(), cos_elevation * phi.sin());
+        Some(LightSample {
+            direction: rotate_y(&direction, self.rotation),
+            distance: f32::MAX,
+            radiance: self.pixel(x, y),
+            pdf: Some(pdf / (2. * pi::<f32>() * pi::<f32>() * cos_elevation)),
+        })
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f32 {
+        let local = rotate_y(direction, -self.rotation);
+        let cos_elevation = (local.x * local.x + local.z * local.z).sqrt();
+        if cos_elevation <= 0. {
+            return 0.;
+        }
+
+        let (x, y) = ImageEnvironment::image_coordinates(&local);
+        self.distribution.pdf(x, y) / (2. * pi::<f32>() * pi::<f32>() * cos_elevation)
+    }
+
+    fn is_black(&self) -> bool {
+        self.strength <= 0.
+    }
+}
+
+/// Relative luminance of a linear RGB colour.
+pub fn luminance(color: &Vec3) -> f32 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+/// Rotate a vector around the Y axis by an angle in radians.
+fn rotate_y(vector: &Vec3, angle: f32) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    Vec3::new(
+        cos * vector.x + sin * vector.z,
+        vector.y,
+        -sin * vector.x + cos * vector.z,
+    )
+}
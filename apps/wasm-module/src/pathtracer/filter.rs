@@ -0,0 +1,154 @@
+use nalgebra_glm::two_pi;
+
+/// Shape of the weights given to the samples around a pixel center.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    /// Same weight everywhere. With a radius of half a pixel, each pixel averages its own samples.
+    Box,
+    /// Weight falling linearly to zero at the radius.
+    Tent,
+    /// Gaussian of standard deviation a third of the radius, shifted to reach zero at the radius.
+    Gaussian,
+    /// Mitchell–Netravali cubic with B = C = 1/3. Its negative lobes sharpen the image.
+    Mitchell,
+    /// Blackman–Harris window, close to a Gaussian with a smoother falloff.
+    BlackmanHarris,
+}
+
+impl Filter {
+    /// Radius giving the usual footprint of the filter, in pixels.
+    pub fn default_radius(&self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.,
+            Filter::BlackmanHarris => 2.,
+        }
+    }
+
+    /// Weight at `t`, the distance from the center divided by the radius, in [0, 1].
+    fn eval(&self, t: f32) -> f32 {
+        match self {
+            Filter::Box => 1.,
+            Filter::Tent => 1. - t,
+            Filter::Gaussian => (-4.5 * t * t).exp() - (-4.5f32).exp(),
+            Filter::Mitchell => mitchell(2. * t, 1. / 3., 1. / 3.),
+            Filter::BlackmanHarris => {
+                // The window spans [0, 1], its peak at the center.
+                let n = two_pi::<f32>() * 0.5 * (1. + t);
+                0.35875 - 0.48829 * n.cos() + 0.14128 * (2. * n).cos() - 0.01168 * (3. * n).cos()
+            }
+        }
+    }
+}
+
+/// Reconstruction filter of the pixels: every sample is shared between the pixels
+/// whose center is closer than `radius` along both axes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelFilter {
+    pub filter: Filter,
+    /// Half the width of the filter, in pixels.
+    pub radius: f32,
+}
+
+impl PixelFilter {
+    pub fn new(filter: Filter, radius: f32) -> PixelFilter {
+        PixelFilter { filter, radius }
+    }
+
+    /// Number of pixels around a tile whose samples reach the pixels of the tile.
+    pub fn margin(&self) -> u32 {
+        (self.radius - 0.5).max(0.).ceil() as u32
+    }
+
+    /// Range of the pixels reached by a sample at the image coordinate `position`.
+    pub fn pixels(&self, position: f32) -> (i64, i64) {
+        let first = (position - 0.5 - self.radius).floor() as i64 + 1;
+        let last = (position - 0.5 + self.radius).floor() as i64;
+        (first, last)
+    }
+
+    /// Weight of a sample at the offset `(dx, dy)` from a pixel center.
+    pub fn weight(&self, dx: f32, dy: f32) -> f32 {
+        if self.radius <= 0. || dx.abs() > self.radius || dy.abs() > self.radius {
+            return 0.;
+        }
+
+        self.filter.eval(dx.abs() / self.radius) * self.filter.eval(dy.abs() / self.radius)
+    }
+}
+
+impl Default for PixelFilter {
+    fn default() -> Self {
+        PixelFilter::new(Filter::Box, 0.5)
+    }
+}
+
+/// Mitchell–Netravali cubic, over [0, 2].
+fn mitchell(x: f32, b: f32, c: f32) -> f32 {
+    let x = x.abs();
+    if x > 2. {
+        0.
+    } else if x > 1. {
+        ((-b - 6. * c) * x * x * x + (6. * b + 30. * c) * x * x + (-12. * b - 48. * c) * x + (8. * b + 24. * c)) / 6.
+    } else {
+        ((12. - 9. * b - 6. * c) * x * x * x + (-18. + 12. * b + 6. * c) * x * x + (6. - 2. * b)) / 6.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [Filter; 5] = [Filter::Box, Filter::Tent, Filter::Gaussian, Filter::Mitchell, Filter::BlackmanHarris];
+
+    #[test]
+    fn margins_cover_the_radius_past_the_tile_pixels() {
+        let margins: Vec<u32> = FILTERS
+            .iter()
+            .map(|&filter| PixelFilter::new(filter, filter.default_radius()).margin())
+            .collect();
+        assert_eq!(margins, vec![0, 1, 1, 2, 2]);
+        assert_eq!(PixelFilter::new(Filter::Box, 0.).margin(), 0);
+    }
+
+    #[test]
+    fn samples_reach_the_pixels_within_the_radius() {
+        assert_eq!(PixelFilter::new(Filter::Box, 0.5).pixels(2.3), (2, 2));
+        assert_eq!(PixelFilter::new(Filter::Tent, 1.).pixels(2.3), (1, 2));
+        assert_eq!(PixelFilter::new(Filter::Mitchell, 2.).pixels(0.), (-2, 1));
+
+        for &filter in &FILTERS {
+            let pixel_filter = PixelFilter::new(filter, filter.default_radius());
+            for step in 0..40 {
+                let position = 10. + step as f32 * 0.025;
+                let (first, last) = pixel_filter.pixels(position);
+                // Samples exactly on the border of two box pixels only go to one of them.
+                for pixel in first - 3..=last + 3 {
+                    let offset = position - (pixel as f32 + 0.5);
+                    let reached = (first..=last).contains(&pixel);
+                    let radius = pixel_filter.radius;
+                    assert_eq!(reached, (-radius..radius).contains(&offset), "{:?} at {}, pixel {}", filter, position, pixel);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn weights_peak_at_the_center_and_vanish_past_the_radius() {
+        for &filter in &FILTERS {
+            let pixel_filter = PixelFilter::new(filter, filter.default_radius());
+            let radius = pixel_filter.radius;
+
+            assert!(pixel_filter.weight(0., 0.) > 0.);
+            assert!(pixel_filter.weight(0., 0.) >= pixel_filter.weight(0.5 * radius, 0.));
+            assert_eq!(pixel_filter.weight(1.01 * radius, 0.), 0.);
+            assert_eq!(pixel_filter.weight(0., -1.01 * radius), 0.);
+            assert_eq!(pixel_filter.weight(0.3, -0.2), pixel_filter.weight(-0.3, 0.2));
+        }
+
+        assert_eq!(PixelFilter::default().weight(0.4, -0.4), 1.);
+        assert_eq!(PixelFilter::new(Filter::Tent, 0.).weight(0., 0.), 0.);
+    }
+}
@@ -0,0 +1,252 @@
+use nalgebra_glm::Vec3;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum HdrError {
+    /// The data doesn't start with the Radiance signature.
+    InvalidSignature,
+    /// The pixels are not stored as RGBE.
+    UnsupportedFormat(String),
+    /// The resolution line is missing or not in the usual `-Y height +X width` order.
+    InvalidResolution(String),
+    /// The data ends before the last pixel.
+    UnexpectedEnd,
+    /// A run length encoded scanline doesn't match the image width.
+    InvalidScanline(usize),
+}
+
+impl fmt::Display for HdrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HdrError::InvalidSignature => write!(f, "not a Radiance HDR file"),
+            HdrError::UnsupportedFormat(format) => write!(f, "unsupported HDR pixel format {}", format),
+            HdrError::InvalidResolution(line) => write!(f, "unsupported HDR resolution line \"{}\"", line),
+            HdrError::UnexpectedEnd => write!(f, "truncated HDR data"),
+            HdrError::InvalidScanline(row) => write!(f, "invalid HDR scanline {}", row),
+        }
+    }
+}
+
+/// Linear RGB pixels, row by row from the top left corner.
+pub struct HdrImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<Vec3>,
+}
+
+impl HdrImage {
+    /// Decode a Radiance `.hdr` file, with flat or run length encoded scanlines.
+    pub fn from_radiance(data: &[u8]) -> Result<HdrImage, HdrError> {
+        let mut reader = Reader { data, position: 0 };
+
+        let signature = reader.line()?;
+        if !signature.starts_with("#?") {
+            return Err(HdrError::InvalidSignature);
+        }
+
+        // The header ends with an empty line.
+        loop {
+            let line = reader.line()?;
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+                return Err(HdrError::UnsupportedFormat(line["FORMAT=".len()..].to_string()));
+            }
+        }
+
+        let resolution = reader.line()?;
+        let (width, height) = match resolution.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            ["-Y", height, "+X", width] => match (width.parse::<u32>(), height.parse::<u32>()) {
+                (Ok(width), Ok(height)) if width > 0 && height > 0 => (width, height),
+                _ => return Err(HdrError::InvalidResolution(resolution)),
+            },
+            _ => return Err(HdrError::InvalidResolution(resolution)),
+        };
+
+        // Reject a resolution that the rest of the data can't hold before allocating it.
+        let pixel_count = (width as usize).checked_mul(height as usize);
+        let size = min_scanline_size(width as usize).and_then(|size| size.checked_mul(height as usize));
+        let pixel_count = match (pixel_count, size) {
+            (Some(pixel_count), Some(size)) if size <= reader.remaining() => pixel_count,
+            _ => return Err(HdrError::UnexpectedEnd),
+        };
+
+        let mut pixels = Vec::with_capacity(pixel_count);
+        let mut scanline = vec![[0u8; 4]; width as usize];
+        for row in 0..height as usize {
+            reader.scanline(&mut scanline, row)?;
+            pixels.extend(scanline.iter().map(rgbe_to_rgb));
+        }
+
+        Ok(HdrImage {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Vec3 {
+        self.pixels[y as usize * self.width as usize + x as usize]
+    }
+}
+
+/// Fewest bytes a scanline of `width` pixels can take: 4 per pixel when flat, or when run
+/// length encoded, its 4 starting bytes and runs of up to 127 pixels in 2 bytes per channel.
+fn min_scanline_size(width: usize) -> Option<usize> {
+    if (8..0x8000).contains(&width) {
+        Some(4 + 4 * 2 * width.div_ceil(127))
+    } else {
+        width.checked_mul(4)
+    }
+}
+
+/// Shared exponent encoding: each channel is a mantissa of `2^(e - 128)`.
+fn rgbe_to_rgb(rgbe: &[u8; 4]) -> Vec3 {
+    if rgbe[3] == 0 {
+        return Vec3::new(0., 0., 0.);
+    }
+
+    let scale = 2f32.powi(i32::from(rgbe[3]) - (128 + 8));
+    Vec3::new(
+        (f32::from(rgbe[0]) + 0.5) * scale,
+        (f32::from(rgbe[1]) + 0.5) * scale,
+        (f32::from(rgbe[2]) + 0.5) * scale,
+    )
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    fn byte(&mut self) -> Result<u8, HdrError> {
+        let byte = *self.data.get(self.position).ok_or(HdrError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    /// Header line, without its line feed.
+    fn line(&mut self) -> Result<String, HdrError> {
+        let start = self.position;
+        while self.byte()? != b'\n' {}
+        Ok(String::from_utf8_lossy(&self.data[start..self.position - 1]).trim().to_string())
+    }
+
+    fn scanline(&mut self, scanline: &mut [[u8; 4]], row: usize) -> Result<(), HdrError> {
+        let width = scanline.len();
+
+        // Run length encoded scanlines start with 2, 2 and their width on two bytes.
+        let start = self.data.get(self.position..self.position + 4).ok_or(HdrError::UnexpectedEnd)?;
+        let is_encoded = (8..0x8000).contains(&width) && start[0] == 2 && start[1] == 2 && start[2] & 0x80 == 0;
+        if !is_encoded {
+            for pixel in scanline.iter_mut() {
+                for channel in pixel.iter_mut() {
+                    *channel = self.byte()?;
+                }
+            }
+            return Ok(());
+        }
+
+        if (usize::from(start[2]) << 8 | usize::from(start[3])) != width {
+            return Err(HdrError::InvalidScanline(row));
+        }
+        self.position += 4;
+
+        // Each channel is encoded separately, as runs of a repeated value
+        // or of literal values.
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = self.byte()?;
+                if count > 128 {
+                    let count = usize::from(count - 128);
+                    let value = self.byte()?;
+                    if x + count > width {
+                        return Err(HdrError::InvalidScanline(row));
+                    }
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[channel] = value;
+                    }
+                    x += count;
+                } else {
+                    let count = usize::from(count);
+                    if count == 0 || x + count > width {
+                        return Err(HdrError::InvalidScanline(row));
+                    }
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[channel] = self.byte()?;
+                    }
+                    x += count;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn radiance(resolution: &str, pixels: &[u8]) -> Vec<u8> {
+        let mut data = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", resolution).into_bytes();
+        data.extend_from_slice(pixels);
+        data
+    }
+
+    #[test]
+    fn decodes_flat_scanlines() {
+        let data = radiance("-Y 2 +X 1", &[128, 64, 0, 129, 0, 0, 0, 0]);
+        let image = HdrImage::from_radiance(&data).unwrap();
+
+        assert_eq!((image.width(), image.height()), (1, 2));
+        assert_eq!(image.pixel(0, 0), Vec3::new(128.5, 64.5, 0.5) / 128.);
+        assert_eq!(image.pixel(0, 1), Vec3::new(0., 0., 0.));
+    }
+
+    #[test]
+    fn decodes_run_length_encoded_scanlines() {
+        // A run of 8 values for red, green and blue, then 8 literal exponents.
+        let mut pixels = vec![2, 2, 0, 8, 128 + 8, 128, 128 + 8, 64, 128 + 8, 0, 8];
+        pixels.extend_from_slice(&[129, 129, 129, 129, 0, 0, 0, 0]);
+        let data = radiance("-Y 1 +X 8", &pixels);
+        let image = HdrImage::from_radiance(&data).unwrap();
+
+        assert_eq!((image.width(), image.height()), (8, 1));
+        assert_eq!(image.pixel(3, 0), Vec3::new(128.5, 64.5, 0.5) / 128.);
+        assert_eq!(image.pixel(4, 0), Vec3::new(0., 0., 0.));
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = radiance("-Y 2 +X 1", &[128, 64, 0, 129, 0, 0, 0]);
+        assert_eq!(HdrImage::from_radiance(&data).err(), Some(HdrError::UnexpectedEnd));
+
+        let data = radiance("-Y 1 +X 8", &[2, 2, 0, 8, 128 + 8, 128, 128 + 8, 64, 128 + 8, 0, 8, 129]);
+        assert_eq!(HdrImage::from_radiance(&data).err(), Some(HdrError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn rejects_resolutions_larger_than_the_data() {
+        let data = radiance("-Y 40000 +X 40000", &[0; 64]);
+        assert_eq!(HdrImage::from_radiance(&data).err(), Some(HdrError::UnexpectedEnd));
+
+        let data = radiance("-Y 4294967295 +X 4294967295", &[0; 64]);
+        assert_eq!(HdrImage::from_radiance(&data).err(), Some(HdrError::UnexpectedEnd));
+    }
+}
@@ -1,79 +1,227 @@
-use nalgebra_glm::Vec3;
-use crate::pathtracer::material::Material;
+use nalgebra_glm::{Vec2, Vec3};
+use crate::pathtracer::material::{Material, MaterialTrait};
 use crate::pathtracer::camera::Ray;
 use crate::pathtracer::sphere::Sphere;
 use crate::pathtracer::triangle::Triangle;
+use crate::pathtracer::bvh::{Aabb, Bvh, Intersection};
+use crate::pathtracer::mesh::MeshInstance;
+use crate::pathtracer::transform::Transform;
 use enum_dispatch::enum_dispatch;
+use rand::rngs::SmallRng;
+use rand::Rng;
+use std::collections::HashSet;
 
 pub struct Hit {
     pub t: f32,
     pub point: Vec3,
     pub normal: Vec3,
+    /// Normal of the surface itself, without the interpolation of the vertex normals, in world space.
+    pub geometric_normal: Vec3,
+    /// Texture coordinates.
+    pub uv: Vec2,
     pub material: Material,
+    /// Id given to the material of the shape, see `Context::set_material_id`.
+    pub material_id: u32,
+    /// Id of the shape that was hit.
+    pub object_id: u32,
+    /// Probability density of `sample_surface` picking this point, per unit of world area.
+    /// Only computed on emissive surfaces, zero elsewhere.
+    pub surface_pdf: f32,
+}
+
+/// A point picked at random on the surface of a shape.
+pub struct SurfaceSample {
+    pub point: Vec3,
+    /// Geometric normal, in world space.
+    pub normal: Vec3,
+    pub uv: Vec2,
+    /// Probability density of picking this point, per unit of world area.
+    pub pdf: f32,
+}
+
+impl Intersection for Hit {
+    fn distance(&self) -> f32 {
+        self.t
+    }
 }
 
 #[enum_dispatch]
 pub enum HitableShape {
     Triangle,
     Sphere,
+    MeshInstance,
 }
 
 #[enum_dispatch(HitableShape)]
 pub trait Hitable {
     fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit>;
     fn id(&self) -> u32;
+    fn bounding_box(&self) -> Aabb;
+    fn set_transform(&mut self, transform: Transform);
+    fn material(&self) -> &Material;
+    /// Pick a point on the surface, used to sample emissive shapes.
+    fn sample_surface(&self, rng: &mut SmallRng) -> SurfaceSample;
 }
 
 pub struct HitableList {
     list: Vec<HitableShape>,
+    bvh: Bvh,
+    /// Shapes were added or removed since the last build of the BVH.
+    dirty: bool,
+    /// Shapes edited in place since the last update, their nodes have to be refitted.
+    edited: HashSet<usize>,
+    /// Index of the shapes with an emissive material, updated with the BVH.
+    emitters: Vec<usize>,
+}
+
+impl Default for HitableList {
+    fn default() -> Self {
+        HitableList::new()
+    }
 }
 
 impl HitableList {
     pub fn new() -> HitableList {
         HitableList {
             list: Vec::<HitableShape>::new(),
+            bvh: Bvh::new(),
+            dirty: false,
+            edited: HashSet::new(),
+            emitters: Vec::new(),
         }
     }
 
     pub fn add(&mut self, hitable: HitableShape) {
         self.list.push(hitable);
+        self.dirty = true;
     }
 
-    pub fn find(&mut self, id: u32) -> Option<&mut HitableShape> {
-        self.list.iter_mut().find(|shape| shape.id() == id)
+    pub fn find(&self, id: u32) -> Option<&HitableShape> {
+        self.list.iter().find(|shape| shape.id() == id)
+    }
+
+    /// Find a shape to edit it. The BVH is refitted on the next `update`.
+    ///
+    /// Ids are expected to be unique: when several shapes share one, only the first
+    /// is returned and refitted.
+    pub fn find_mut(&mut self, id: u32) -> Option<&mut HitableShape> {
+        let index = self.list.iter().position(|shape| shape.id() == id)?;
+        self.edited.insert(index);
+        Some(&mut self.list[index])
     }
 
     pub fn remove(&mut self, id: u32) {
+        let len = self.list.len();
         self.list.retain(|shape| shape.id() != id);
+        if self.list.len() != len {
+            self.dirty = true;
+        }
+    }
+
+    /// Bring the BVH up to date with the shapes.
+    ///
+    /// Edited shapes only refit the tree, unless its quality degraded too much.
+    /// Added or removed shapes always trigger a full rebuild.
+    pub fn update(&mut self) {
+        if !self.dirty && self.edited.is_empty() {
+            return;
+        }
+
+        if !self.dirty {
+            let list = &self.list;
+            let primitives: Vec<usize> = self.edited.iter().cloned().collect();
+            self.bvh.refit(|i| list[i].bounding_box(), &primitives);
+            self.dirty = self.bvh.is_degraded();
+        }
+
+        if self.dirty {
+            let bounds: Vec<Aabb> = self.list.iter().map(|shape| shape.bounding_box()).collect();
+            self.bvh = Bvh::build(&bounds);
+        }
+
+        // Materials may have changed as well.
+        self.emitters = self.list.iter()
+            .enumerate()
+            .filter(|(_, shape)| shape.material().is_emissive())
+            .map(|(i, _)| i)
+            .collect();
+
+        self.dirty = false;
+        self.edited.clear();
+    }
+
+    pub fn emitter_count(&self) -> usize {
+        self.emitters.len()
+    }
+
+    /// Emissive shape by index in [0, emitter_count[.
+    pub fn emitter(&self, index: usize) -> Option<&HitableShape> {
+        self.emitters.get(index).and_then(|&i| self.list.get(i))
+    }
+
+    /// Pick an emissive shape uniformly.
+    pub fn pick_emitter(&self, rng: &mut SmallRng) -> Option<&HitableShape> {
+        if self.emitters.is_empty() {
+            None
+        } else {
+            self.emitter(rng.gen_range(0, self.emitters.len()))
+        }
+    }
+
+    fn is_up_to_date(&self) -> bool {
+        !self.dirty && self.edited.is_empty()
     }
 
     pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
-        let mut closest_so_far = t_max;
-        let mut closest_hit: Option<Hit> = None;
-
-        for hitable in &self.list {
-            let hit = hitable.hit(ray, t_min, closest_so_far);
-            if hit.is_some() {
-                let hit = hit.unwrap();
-                closest_so_far = hit.t;
-                closest_hit = Some(hit);
+        // The BVH is out of date until the next update, test every shape meanwhile.
+        if !self.is_up_to_date() {
+            let mut closest_so_far = t_max;
+            let mut closest_hit: Option<Hit> = None;
+
+            for hitable in &self.list {
+                if let Some(hit) = hitable.hit(ray, t_min, closest_so_far) {
+                    closest_so_far = hit.t;
+                    closest_hit = Some(hit);
+                }
             }
+
+            return closest_hit;
+        }
+
+        let list = &self.list;
+        self.bvh.hit(ray, t_min, t_max, |i, closest_so_far| {
+            list[i].hit(ray, t_min, closest_so_far)
+        })
+    }
+
+    /// Check if anything is between `t_min` and `t_max` along the ray.
+    pub fn occluded(&self, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        if !self.is_up_to_date() {
+            return self.list.iter().any(|shape| shape.hit(ray, t_min, t_max).is_some());
         }
 
-        closest_hit
+        let list = &self.list;
+        self.bvh.any_hit(ray, t_min, t_max, |i| list[i].hit(ray, t_min, t_max).is_some())
     }
 
     pub fn stats(&self) -> String {
         let mut spheres = 0;
         let mut triangles = 0;
+        let mut instances = 0;
+        let mut instanced_triangles = 0;
 
         for shape in &self.list {
-            match *shape {
-                HitableShape::Sphere(_) => spheres = spheres + 1,
-                HitableShape::Triangle(_) => triangles = triangles + 1,
+            match shape {
+                HitableShape::Sphere(_) => spheres += 1,
+                HitableShape::Triangle(_) => triangles += 1,
+                HitableShape::MeshInstance(instance) => {
+                    instances += 1;
+                    instanced_triangles += instance.mesh.triangle_count();
+                },
             }
         }
 
-        format!("{} shapes:\n * {} spheres\n * {} triangles", self.list.len(), spheres, triangles)
+        format!("{} shapes:\n * {} spheres\n * {} triangles\n * {} mesh instances ({} triangles)\n * {} BVH nodes",
+                self.list.len(), spheres, triangles, instances, instanced_triangles, self.bvh.node_count())
     }
 }
@@ -0,0 +1,104 @@
+use crate::pathtracer::arealight::{DiskLight, RectLight};
+use crate::pathtracer::camera::Ray;
+use crate::pathtracer::directionallight::DirectionalLight;
+use crate::pathtracer::pointlight::PointLight;
+use crate::pathtracer::spotlight::SpotLight;
+use enum_dispatch::enum_dispatch;
+use nalgebra_glm::Vec3;
+use rand::rngs::SmallRng;
+
+/// Light arriving at a point from a sampled light.
+pub struct LightSample {
+    /// Unit vector from the shading point to the light.
+    pub direction: Vec3,
+    /// Length of the shadow ray.
+    pub distance: f32,
+    pub radiance: Vec3,
+    /// Density of the direction, per unit of solid angle.
+    /// Lights without area can only be reached by sampling them and have no density.
+    pub pdf: Option<f32>,
+}
+
+/// A light that isn't part of the geometry.
+#[enum_dispatch(Light)]
+pub trait LightTrait {
+    fn id(&self) -> u32;
+
+    /// Pick a direction towards the light as seen from `point`.
+    fn sample(&self, point: &Vec3, rng: &mut SmallRng) -> Option<LightSample>;
+
+    /// Density of `sample` picking the unit vector `direction` from `point`,
+    /// per unit of solid angle. Zero for lights without area.
+    fn pdf(&self, _point: &Vec3, _direction: &Vec3) -> f32 {
+        0.
+    }
+
+    /// Radiance seen along a ray that reaches the light before `t_max`.
+    /// Lights without area can't be hit.
+    fn hit(&self, _ray: &Ray, _t_max: f32) -> Option<Vec3> {
+        None
+    }
+}
+
+#[enum_dispatch]
+pub enum Light {
+    PointLight,
+    SpotLight,
+    DirectionalLight,
+    RectLight,
+    DiskLight,
+}
+
+pub struct LightList {
+    list: Vec<Light>,
+}
+
+impl Default for LightList {
+    fn default() -> Self {
+        LightList::new()
+    }
+}
+
+impl LightList {
+    pub fn new() -> LightList {
+        LightList {
+            list: Vec::<Light>::new(),
+        }
+    }
+
+    pub fn add(&mut self, light: Light) {
+        self.list.push(light);
+    }
+
+    /// Add a light, or replace the light with the same id.
+    pub fn add_or_replace(&mut self, light: Light) {
+        match self.find(light.id()) {
+            Some(existing) => *existing = light,
+            None => self.add(light),
+        }
+    }
+
+    pub fn find(&mut self, id: u32) -> Option<&mut Light> {
+        self.list.iter_mut().find(|light| light.id() == id)
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        self.list.retain(|light| light.id() != id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> &Light {
+        &self.list[index]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Light> {
+        self.list.iter()
+    }
+}
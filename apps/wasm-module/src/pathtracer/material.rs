@@ -1,14 +1,48 @@
 use crate::pathtracer::camera::Ray;
 use crate::pathtracer::hit::Hit;
-use crate::pathtracer::random_in_unit_sphere;
+use crate::pathtracer::{random_in_unit_sphere, random_unit_vector};
+use crate::pathtracer::texture::{Texture, TextureTrait};
 use enum_dispatch::enum_dispatch;
-use nalgebra_glm::Vec3;
+use nalgebra_glm::{Vec2, Vec3};
 use rand::rngs::SmallRng;
 use rand::Rng;
 
 #[enum_dispatch(Material)]
 pub trait MaterialTrait {
     fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut SmallRng) -> Option<ScatterResult>;
+
+    /// BRDF for light arriving from `wi` and leaving towards `wo`, both unit vectors
+    /// pointing away from the surface. The cosine term is not included.
+    /// Specular materials return zero: no light direction can be sampled for them.
+    fn eval(&self, _wo: &Vec3, _wi: &Vec3, _hit: &Hit) -> Vec3 {
+        Vec3::new(0., 0., 0.)
+    }
+
+    /// Density, per unit of solid angle, of `scatter` picking the direction `wi`.
+    fn pdf(&self, _wo: &Vec3, _wi: &Vec3, _hit: &Hit) -> f32 {
+        0.
+    }
+
+    /// Specular surfaces only reflect light along a few directions.
+    /// The direct lighting skips them, the lights are found by the scattered rays instead.
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    /// Radiance emitted by the surface.
+    fn emitted(&self, _uv: &Vec2, _point: &Vec3) -> Vec3 {
+        Vec3::new(0., 0., 0.)
+    }
+
+    /// Emissive surfaces are sampled as lights.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// Overall colour of the surface, for the albedo pass.
+    fn albedo(&self, _uv: &Vec2, _point: &Vec3) -> Vec3 {
+        Vec3::new(1., 1., 1.)
+    }
 }
 
 #[enum_dispatch]
@@ -17,6 +51,7 @@ pub enum Material {
     LambertianMaterial,
     MetalMaterial,
     DielectricMaterial,
+    EmissiveMaterial,
 }
 
 pub struct ScatterResult {
@@ -26,32 +61,64 @@ pub struct ScatterResult {
 
 #[derive(Clone)]
 pub struct LambertianMaterial {
-    pub albedo: Vec3,
+    pub albedo: Texture,
+}
+
+/// Normal on the side of the surface the direction `wo` is on.
+fn facing_normal(wo: &Vec3, normal: &Vec3) -> Vec3 {
+    if wo.dot(normal) < 0. {
+        -normal
+    } else {
+        *normal
+    }
 }
 
 impl MaterialTrait for LambertianMaterial {
-    fn scatter(&self, _ray: &Ray, hit: &Hit, rng: &mut SmallRng) -> Option<ScatterResult> {
-        let target: Vec3 = hit.point + hit.normal + random_in_unit_sphere(rng);
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut SmallRng) -> Option<ScatterResult> {
+        // The normal offset by a unit vector gives directions distributed by the cosine,
+        // so the cosine and the density cancel out and only the albedo is left.
+        let normal = facing_normal(&-ray.direction, &hit.normal);
+        let mut direction: Vec3 = normal + random_unit_vector(rng);
+        if direction.magnitude_squared() < 1e-8 {
+            direction = normal;
+        }
+
         let scattered = Ray {
             origin: hit.point,
-            direction: target - hit.point,
+            direction: direction.normalize(),
         };
-        let attenuation = self.albedo;
+        let attenuation = self.albedo.value(&hit.uv, &hit.point);
         Some(ScatterResult {
             attenuation,
             scattered,
         })
     }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> Vec3 {
+        if wi.dot(&facing_normal(wo, &hit.normal)) <= 0. {
+            return Vec3::new(0., 0., 0.);
+        }
+
+        self.albedo.value(&hit.uv, &hit.point) / std::f32::consts::PI
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> f32 {
+        wi.dot(&facing_normal(wo, &hit.normal)).max(0.) / std::f32::consts::PI
+    }
+
+    fn albedo(&self, uv: &Vec2, point: &Vec3) -> Vec3 {
+        self.albedo.value(uv, point)
+    }
 }
 
 #[derive(Clone)]
 pub struct MetalMaterial {
-    pub albedo: Vec3,
+    pub albedo: Texture,
     pub fuzz: f32,
 }
 
 fn reflect(v: &Vec3, normal: &Vec3) -> Vec3 {
-    v - 2. * v.dot(&normal) * normal
+    v - 2. * v.dot(normal) * normal
 }
 
 impl MaterialTrait for MetalMaterial {
@@ -61,7 +128,7 @@ impl MaterialTrait for MetalMaterial {
             origin: hit.point,
             direction: reflected + self.fuzz * random_in_unit_sphere(rng),
         };
-        let attenuation = self.albedo;
+        let attenuation = self.albedo.value(&hit.uv, &hit.point);
 
         if scattered.direction.dot(&hit.normal) > 0. {
             Some(ScatterResult {
@@ -72,6 +139,50 @@ impl MaterialTrait for MetalMaterial {
             None
         }
     }
+
+    /// The BRDF implied by `scatter`: the albedo times the density of the scattered
+    /// directions, divided by the cosine.
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> Vec3 {
+        let cosine = wi.dot(&hit.normal);
+        if cosine <= 0. {
+            return Vec3::new(0., 0., 0.);
+        }
+
+        self.albedo.value(&hit.uv, &hit.point) * (self.pdf(wo, wi, hit) / cosine)
+    }
+
+    /// The scattered direction points to a uniform point of the ball of radius `fuzz`
+    /// around the mirror direction. Its density is the volume of the ball seen along `wi`,
+    /// the integral of `t²` over the part of the ray inside the ball, divided by the volume of the ball.
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> f32 {
+        if self.fuzz <= 0. || wi.dot(&hit.normal) <= 0. {
+            return 0.;
+        }
+
+        let reflected = reflect(&-wo, &hit.normal);
+        let along = wi.dot(&reflected);
+        let discriminant = along * along - 1. + self.fuzz * self.fuzz;
+        if discriminant <= 0. {
+            return 0.;
+        }
+
+        let far = along + discriminant.sqrt();
+        let near = (along - discriminant.sqrt()).max(0.);
+        if far <= 0. {
+            return 0.;
+        }
+
+        (far * far * far - near * near * near) / (4. * std::f32::consts::PI * self.fuzz * self.fuzz * self.fuzz)
+    }
+
+    /// Only perfect mirrors, the fuzzy reflections can be lit by sampling the lights.
+    fn is_specular(&self) -> bool {
+        self.fuzz <= 0.
+    }
+
+    fn albedo(&self, uv: &Vec2, point: &Vec3) -> Vec3 {
+        self.albedo.value(uv, point)
+    }
 }
 
 #[derive(Clone)]
@@ -81,7 +192,7 @@ pub struct DielectricMaterial {
 
 pub fn refract(v: &Vec3, normal: &Vec3, ni_over_nt: f32) -> Option<Vec3> {
     let uv: Vec3 = v.normalize();
-    let dt: f32 = uv.dot(&normal);
+    let dt: f32 = uv.dot(normal);
     let discriminant: f32 = 1.0 - ni_over_nt * ni_over_nt * (1. - dt * dt);
     if discriminant > 0. {
         Some(ni_over_nt * (uv - normal * dt) - normal * discriminant.sqrt())
@@ -117,7 +228,7 @@ impl MaterialTrait for DielectricMaterial {
 
         let refracted = refract(&ray.direction, &outward_normal, ni_over_nt);
 
-        if refracted.is_some() {
+        if let Some(refracted) = refracted {
             let reflect_prob = schlick(cosine, self.refract_index);
             if rng.gen_range(0., 1.) < reflect_prob {
                 scattered = Ray {
@@ -127,7 +238,7 @@ impl MaterialTrait for DielectricMaterial {
             } else {
                 scattered = Ray {
                     origin: hit.point,
-                    direction: refracted.unwrap(),
+                    direction: refracted,
                 };
             }
         } else {
@@ -142,4 +253,33 @@ impl MaterialTrait for DielectricMaterial {
             scattered,
         })
     }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+/// A surface emitting light on both of its sides. It doesn't reflect anything.
+#[derive(Clone)]
+pub struct EmissiveMaterial {
+    pub emission: Texture,
+    pub strength: f32,
+}
+
+impl MaterialTrait for EmissiveMaterial {
+    fn scatter(&self, _ray: &Ray, _hit: &Hit, _rng: &mut SmallRng) -> Option<ScatterResult> {
+        None
+    }
+
+    fn emitted(&self, uv: &Vec2, point: &Vec3) -> Vec3 {
+        self.strength * self.emission.value(uv, point)
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self, uv: &Vec2, point: &Vec3) -> Vec3 {
+        self.emission.value(uv, point)
+    }
 }
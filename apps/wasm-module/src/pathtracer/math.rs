@@ -1,5 +1,8 @@
 use nalgebra_glm::clamp_vec;
-use nalgebra_glm::Vec3;
+use crate::pathtracer::texture::srgb_to_linear;
+use nalgebra_glm::{two_pi, Vec3};
+use rand::rngs::SmallRng;
+use rand::Rng;
 
 pub fn clamp(value: f32, min: f32, max: f32) -> f32 {
     if value > max {
@@ -15,4 +18,76 @@ pub fn saturate(value: Vec3) -> Vec3 {
     let min_bounds = Vec3::new(0.0, 0.0, 0.0);
     let max_bounds = Vec3::new(1.0, 1.0, 1.0);
     clamp_vec(&value, &min_bounds, &max_bounds)
-}
\ No newline at end of file
+}
+
+/// Weight of a sample drawn with density `pdf`, when another strategy with density
+/// `other_pdf` could have drawn it too.
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let pdf_squared = pdf * pdf;
+    let sum = pdf_squared + other_pdf * other_pdf;
+    if sum > 0. {
+        pdf_squared / sum
+    } else {
+        0.
+    }
+}
+
+/// Linear RGB colour of a black body at the given temperature, in Kelvin,
+/// with its brightest channel at one.
+///
+/// Fit of the blackbody colours by Tanner Helland, valid from 1000K to 40000K.
+pub fn kelvin_to_rgb(kelvin: f32) -> Vec3 {
+    let temperature = clamp(kelvin, 1000., 40000.) / 100.;
+
+    let red = if temperature <= 66. {
+        255.
+    } else {
+        329.6987 * (temperature - 60.).powf(-0.1332048)
+    };
+
+    let green = if temperature <= 66. {
+        99.4708 * temperature.ln() - 161.1196
+    } else {
+        288.1222 * (temperature - 60.).powf(-0.07551485)
+    };
+
+    let blue = if temperature >= 66. {
+        255.
+    } else if temperature <= 19. {
+        0.
+    } else {
+        138.5177 * (temperature - 10.).ln() - 305.0448
+    };
+
+    // The fit gives sRGB encoded 8-bit values.
+    let color = Vec3::new(red, green, blue).map(|channel| srgb_to_linear(clamp(channel, 0., 255.) / 255.));
+    color / color.max()
+}
+
+/// Hermite interpolation from 0 at `edge0` to 1 at `edge1`.
+pub fn smoothstep(edge0: f32, edge1: f32, value: f32) -> f32 {
+    let t = clamp((value - edge0) / (edge1 - edge0), 0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+/// Two unit vectors perpendicular to the unit vector `normal` and to each other.
+pub fn orthonormal_basis(normal: &Vec3) -> (Vec3, Vec3) {
+    // Source: Duff et al., Building an Orthonormal Basis, Revisited.
+    let sign = 1f32.copysign(normal.z);
+    let a = -1. / (sign + normal.z);
+    let b = normal.x * normal.y * a;
+    (
+        Vec3::new(1. + sign * normal.x * normal.x * a, sign * b, -sign * normal.x),
+        Vec3::new(b, sign + normal.y * normal.y * a, -normal.y),
+    )
+}
+
+/// Unit vector picked uniformly in the cone around the unit vector `axis`
+/// whose half angle has the cosine `cos_max`.
+pub fn sample_cone(axis: &Vec3, cos_max: f32, rng: &mut SmallRng) -> Vec3 {
+    let cos_theta = 1. - rng.gen_range(0., 1.) * (1. - cos_max);
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = rng.gen_range(0., two_pi::<f32>());
+    let (tangent, bitangent) = orthonormal_basis(axis);
+    (sin_theta * phi.cos()) * tangent + (sin_theta * phi.sin()) * bitangent + cos_theta * axis
+}
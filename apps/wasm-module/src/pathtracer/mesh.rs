@@ -0,0 +1,576 @@
+use crate::pathtracer::bvh::{Aabb, Bvh, Intersection};
+use crate::pathtracer::camera::Ray;
+use crate::pathtracer::hit::{Hit, Hitable, SurfaceSample};
+use crate::pathtracer::material::{Material, MaterialTrait};
+use crate::pathtracer::math::clamp;
+use crate::pathtracer::triangle::{intersect_triangle, sample_triangle, triangle_bounding_box};
+use rand::rngs::SmallRng;
+use rand::Rng;
+use crate::pathtracer::transform::Transform;
+use nalgebra_glm::{Vec2, Vec3};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Indexed triangle mesh uploaded once and shared by all its instances.
+///
+/// The mesh lives in its own space and has its own BVH (the bottom level),
+/// instances place it in the world and are stored in the world BVH (the top level).
+pub struct Mesh {
+    /// Vertices, shared by the triangles.
+    positions: Vec<Vec3>,
+    /// Normal of each vertex, interpolated over the triangles for smooth shading.
+    normals: Vec<Vec3>,
+    /// Texture coordinates of each vertex, may be empty.
+    uvs: Vec<Vec2>,
+    /// Three vertex indices per triangle.
+    triangles: Vec<[u32; 3]>,
+    bvh: Bvh,
+    /// Cumulated area of the triangles, to pick them proportionally to their area.
+    area_cdf: Vec<f32>,
+    /// Material used by the instances that don't override it.
+    pub material: Material,
+}
+
+/// Reasons why a mesh cannot be built from the buffers sent by the viewer.
+#[derive(Debug, PartialEq)]
+pub enum MeshError {
+    /// The position buffer length is not a multiple of 3.
+    InvalidPositionCount(usize),
+    /// Without an index buffer, the number of vertices is not a multiple of 3.
+    InvalidVertexCount(usize),
+    /// The index buffer length is not a multiple of 3.
+    InvalidIndexCount(usize),
+    /// The normal buffer doesn't have one normal per vertex.
+    InvalidNormalCount { normal_count: usize, vertex_count: usize },
+    /// The texture coordinate buffer doesn't have one pair per vertex.
+    InvalidUvCount { uv_count: usize, vertex_count: usize },
+    /// A triangle references a vertex that doesn't exist.
+    IndexOutOfRange { index: u32, vertex_count: usize },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshError::InvalidPositionCount(count) => {
+                write!(f, "{} position components is not a multiple of 3", count)
+            }
+            MeshError::InvalidVertexCount(count) => {
+                write!(f, "{} vertices without indices is not a multiple of 3", count)
+            }
+            MeshError::InvalidIndexCount(count) => {
+                write!(f, "{} indices is not a multiple of 3", count)
+            }
+            MeshError::InvalidNormalCount { normal_count, vertex_count } => {
+                write!(f, "{} normal components for {} vertices", normal_count, vertex_count)
+            }
+            MeshError::InvalidUvCount { uv_count, vertex_count } => {
+                write!(f, "{} texture coordinates for {} vertices", uv_count, vertex_count)
+            }
+            MeshError::IndexOutOfRange { index, vertex_count } => {
+                write!(f, "index {} is out of range for {} vertices", index, vertex_count)
+            }
+        }
+    }
+}
+
+/// Intersection of a ray with a triangle of a mesh.
+pub struct MeshHit {
+    pub triangle: usize,
+    pub t: f32,
+    pub u: f32,
+    pub v: f32,
+}
+
+impl Intersection for MeshHit {
+    fn distance(&self) -> f32 {
+        self.t
+    }
+}
+
+impl Mesh {
+    /// Build a mesh from a position buffer (3 floats per vertex), a normal buffer
+    /// (3 floats per vertex), a texture coordinate buffer (2 floats per vertex)
+    /// and an index buffer (3 indices per triangle), as found in glTF and Three.js geometries.
+    ///
+    /// An empty index buffer means the mesh is not indexed: every 3 consecutive vertices form a triangle.
+    /// An empty normal buffer means the normals are computed from the triangles sharing each vertex.
+    /// An empty texture coordinate buffer means the barycentric coordinates are used instead.
+    pub fn new<I>(
+        positions: &[f32],
+        normals: &[f32],
+        uvs: &[f32],
+        indices: &[I],
+        material: Material,
+    ) -> Result<Mesh, MeshError>
+    where
+        I: Copy + Into<u32>,
+    {
+        if !positions.len().is_multiple_of(3) {
+            return Err(MeshError::InvalidPositionCount(positions.len()));
+        }
+
+        let positions: Vec<Vec3> = positions
+            .chunks_exact(3)
+            .map(|position| Vec3::new(position[0], position[1], position[2]))
+            .collect();
+
+        let triangles: Vec<[u32; 3]> = if indices.is_empty() {
+            if !positions.len().is_multiple_of(3) {
+                return Err(MeshError::InvalidVertexCount(positions.len()));
+            }
+
+            (0..positions.len() as u32 / 3)
+                .map(|i| [3 * i, 3 * i + 1, 3 * i + 2])
+                .collect()
+        } else {
+            if !indices.len().is_multiple_of(3) {
+                return Err(MeshError::InvalidIndexCount(indices.len()));
+            }
+
+            let mut triangles = Vec::with_capacity(indices.len() / 3);
+            for triangle in indices.chunks_exact(3) {
+                let triangle = [triangle[0].into(), triangle[1].into(), triangle[2].into()];
+                for &index in &triangle {
+                    if index as usize >= positions.len() {
+                        return Err(MeshError::IndexOutOfRange {
+                            index,
+                            vertex_count: positions.len(),
+                        });
+                    }
+                }
+                triangles.push(triangle);
+            }
+            triangles
+        };
+
+        let normals = if normals.is_empty() {
+            vertex_normals(&positions, &triangles)
+        } else {
+            if normals.len() != 3 * positions.len() {
+                return Err(MeshError::InvalidNormalCount {
+                    normal_count: normals.len(),
+                    vertex_count: positions.len(),
+                });
+            }
+
+            normals
+                .chunks_exact(3)
+                .map(|normal| Vec3::new(normal[0], normal[1], normal[2]))
+                .collect()
+        };
+
+        if !uvs.is_empty() && uvs.len() != 2 * positions.len() {
+            return Err(MeshError::InvalidUvCount {
+                uv_count: uvs.len(),
+                vertex_count: positions.len(),
+            });
+        }
+        let uvs = uvs
+            .chunks_exact(2)
+            .map(|uv| Vec2::new(uv[0], uv[1]))
+            .collect();
+
+        let bounds: Vec<Aabb> = triangles
+            .iter()
+            .map(|&[a, b, c]| {
+                triangle_bounding_box(
+                    &positions[a as usize],
+                    &positions[b as usize],
+                    &positions[c as usize],
+                )
+            })
+            .collect();
+
+        let mut area = 0.;
+        let area_cdf = triangles
+            .iter()
+            .map(|&[a, b, c]| {
+                let edge_ab = positions[b as usize] - positions[a as usize];
+                let edge_ac = positions[c as usize] - positions[a as usize];
+                area += 0.5 * edge_ab.cross(&edge_ac).magnitude();
+                area
+            })
+            .collect();
+
+        Ok(Mesh {
+            positions,
+            normals,
+            uvs,
+            triangles,
+            bvh: Bvh::build(&bounds),
+            area_cdf,
+            material,
+        })
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// Bounding box in mesh space.
+    pub fn bounding_box(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+
+    /// Vertices of a triangle, in mesh space.
+    fn vertices(&self, triangle: usize) -> (&Vec3, &Vec3, &Vec3) {
+        let [a, b, c] = self.triangles[triangle];
+        (
+            &self.positions[a as usize],
+            &self.positions[b as usize],
+            &self.positions[c as usize],
+        )
+    }
+
+    /// Geometric normal of a triangle, in mesh space.
+    pub fn normal(&self, triangle: usize) -> Vec3 {
+        let (a, b, c) = self.vertices(triangle);
+        (b - a).cross(&(c - a))
+    }
+
+    /// Shading normal at a hit point, interpolated from the vertex normals, in mesh space.
+    /// It is kept on the same side of the triangle as the geometric normal.
+    pub fn shading_normal(&self, hit: &MeshHit) -> Vec3 {
+        let [a, b, c] = self.triangles[hit.triangle];
+        let normal = (1.0 - hit.u - hit.v) * self.normals[a as usize]
+            + hit.u * self.normals[b as usize]
+            + hit.v * self.normals[c as usize];
+
+        let geometric_normal = self.normal(hit.triangle);
+        if normal.dot(&geometric_normal) < 0.0 {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    /// Texture coordinates at a hit point.
+    pub fn uv(&self, hit: &MeshHit) -> Vec2 {
+        if self.uvs.is_empty() {
+            return Vec2::new(hit.u, hit.v);
+        }
+
+        let [a, b, c] = self.triangles[hit.triangle];
+        (1.0 - hit.u - hit.v) * self.uvs[a as usize]
+            + hit.u * self.uvs[b as usize]
+            + hit.v * self.uvs[c as usize]
+    }
+
+    /// Total area of the triangles, in mesh space.
+    pub fn area(&self) -> f32 {
+        match self.area_cdf.last() {
+            Some(&area) => area,
+            None => 0.,
+        }
+    }
+
+    /// Pick a triangle proportionally to its area, then a point on it.
+    /// The returned hit holds the triangle and the barycentric coordinates of the point.
+    pub fn sample_surface(&self, rng: &mut SmallRng) -> Option<(MeshHit, Vec3)> {
+        let area = self.area();
+        if area <= 0. {
+            return None;
+        }
+
+        let target = rng.gen_range(0., area);
+        let triangle = match self
+            .area_cdf
+            .binary_search_by(|probe| probe.partial_cmp(&target).unwrap_or(Ordering::Less))
+        {
+            Ok(i) => i,
+            Err(i) => i.min(self.triangles.len() - 1),
+        };
+
+        let (u, v) = sample_triangle(rng);
+        let (a, b, c) = self.vertices(triangle);
+        let point = (1. - u - v) * a + u * b + v * c;
+
+        Some((MeshHit { triangle, t: 0., u, v }, point))
+    }
+
+    /// Intersect a ray expressed in mesh space.
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<MeshHit> {
+        self.bvh.hit(ray, t_min, t_max, |i, closest_so_far| {
+            let (a, b, c) = self.vertices(i);
+            intersect_triangle(a, b, c, ray, t_min, closest_so_far).map(|hit| MeshHit {
+                triangle: i,
+                t: hit.t,
+                u: hit.u,
+                v: hit.v,
+            })
+        })
+    }
+}
+
+/// Angle-weighted average of the normals of the triangles around each vertex.
+///
+/// Vertices are not welded: a mesh that is not indexed keeps flat normals.
+fn vertex_normals(positions: &[Vec3], triangles: &[[u32; 3]]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::new(0.0, 0.0, 0.0); positions.len()];
+
+    for triangle in triangles {
+        let corners = [
+            positions[triangle[0] as usize],
+            positions[triangle[1] as usize],
+            positions[triangle[2] as usize],
+        ];
+
+        let face_normal = (corners[1] - corners[0]).cross(&(corners[2] - corners[0]));
+        let length = face_normal.magnitude();
+        if length <= 0.0 {
+            continue;
+        }
+        let face_normal = face_normal / length;
+
+        for corner in 0..3 {
+            let edge_a = corners[(corner + 1) % 3] - corners[corner];
+            let edge_b = corners[(corner + 2) % 3] - corners[corner];
+            let cosine = edge_a.normalize().dot(&edge_b.normalize());
+            let angle = clamp(cosine, -1.0, 1.0).acos();
+            normals[triangle[corner] as usize] += angle * face_normal;
+        }
+    }
+
+    for normal in &mut normals {
+        let length = normal.magnitude();
+        if length > 0.0 {
+            *normal /= length;
+        }
+    }
+
+    normals
+}
+
+/// Meshes available for instancing, by id.
+pub struct MeshRegistry {
+    meshes: HashMap<u32, Rc<Mesh>>,
+}
+
+impl Default for MeshRegistry {
+    fn default() -> Self {
+        MeshRegistry::new()
+    }
+}
+
+impl MeshRegistry {
+    pub fn new() -> MeshRegistry {
+        MeshRegistry {
+            meshes: HashMap::new(),
+        }
+    }
+
+    /// Register a mesh, replacing any mesh with the same id.
+    /// Instances of the replaced mesh keep using it until they are removed.
+    pub fn add(&mut self, id: u32, mesh: Mesh) -> Rc<Mesh> {
+        let mesh = Rc::new(mesh);
+        self.meshes.insert(id, mesh.clone());
+        mesh
+    }
+
+    pub fn find(&self, id: u32) -> Option<Rc<Mesh>> {
+        self.meshes.get(&id).cloned()
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        self.meshes.remove(&id);
+    }
+}
+
+/// A mesh placed in the world with its own transform and, optionally, its own material.
+pub struct MeshInstance {
+    id: u32,
+    pub mesh: Rc<Mesh>,
+    /// Overrides the material of the mesh.
+    pub material: Option<Material>,
+    /// Id of the material, for the material id pass.
+    pub material_id: u32,
+    transform: Transform,
+    bounds: Aabb,
+}
+
+impl MeshInstance {
+    pub fn new(id: u32, mesh: Rc<Mesh>, transform: Transform) -> MeshInstance {
+        let bounds = transform.aabb_to_world(&mesh.bounding_box());
+        MeshInstance {
+            id,
+            mesh,
+            material: None,
+            material_id: 0,
+            transform,
+            bounds,
+        }
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    /// Density of a uniform sample on the mesh around a point of the given triangle,
+    /// per unit of world area.
+    fn surface_pdf(&self, triangle: usize) -> f32 {
+        let local_normal = self.mesh.normal(triangle).normalize();
+        1. / (self.mesh.area() * self.transform.area_scale(&local_normal))
+    }
+}
+
+impl Hitable for MeshInstance {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+        let mesh_hit = self.mesh.hit(&self.transform.ray_to_local(ray), t_min, t_max)?;
+
+        let material = self.material().clone();
+        let surface_pdf = if material.is_emissive() {
+            self.surface_pdf(mesh_hit.triangle)
+        } else {
+            0.
+        };
+
+        Some(Hit {
+            t: mesh_hit.t,
+            point: ray.point_at_parameter(mesh_hit.t),
+            normal: self.transform.normal_to_world(&self.mesh.shading_normal(&mesh_hit)),
+            geometric_normal: self.transform.normal_to_world(&self.mesh.normal(mesh_hit.triangle)),
+            uv: self.mesh.uv(&mesh_hit),
+            material,
+            material_id: self.material_id,
+            object_id: self.id,
+            surface_pdf,
+        })
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+
+    /// Move the instance. The world BVH is refitted on its next update.
+    fn set_transform(&mut self, transform: Transform) {
+        self.bounds = transform.aabb_to_world(&self.mesh.bounding_box());
+        self.transform = transform;
+    }
+
+    fn material(&self) -> &Material {
+        match &self.material {
+            Some(material) => material,
+            None => &self.mesh.material,
+        }
+    }
+
+    fn sample_surface(&self, rng: &mut SmallRng) -> SurfaceSample {
+        match self.mesh.sample_surface(rng) {
+            Some((mesh_hit, point)) => {
+                let local_normal = self.mesh.normal(mesh_hit.triangle).normalize();
+                SurfaceSample {
+                    point: self.transform.point_to_world(&point),
+                    normal: self.transform.normal_to_world(&local_normal),
+                    uv: self.mesh.uv(&mesh_hit),
+                    pdf: self.surface_pdf(mesh_hit.triangle),
+                }
+            }
+            // An empty mesh cannot emit anything.
+            None => SurfaceSample {
+                point: self.transform.point_to_world(&Vec3::new(0., 0., 0.)),
+                normal: Vec3::new(0., 1., 0.),
+                uv: Vec2::new(0., 0.),
+                pdf: 0.,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathtracer::material::LambertianMaterial;
+
+    /// Unit square in the XY plane, made of two triangles.
+    const SQUARE: [f32; 12] = [0., 0., 0., 1., 0., 0., 1., 1., 0., 0., 1., 0.];
+
+    fn material() -> Material {
+        LambertianMaterial {
+            albedo: Vec3::new(0.5, 0.5, 0.5).into(),
+        }.into()
+    }
+
+    fn build<I: Copy + Into<u32>>(positions: &[f32], normals: &[f32], uvs: &[f32], indices: &[I]) -> Result<Mesh, MeshError> {
+        Mesh::new(positions, normals, uvs, indices, material())
+    }
+
+    #[test]
+    fn builds_indexed_meshes() {
+        let mesh = build(&SQUARE, &[], &[0., 0., 1., 0., 1., 1., 0., 1.], &[0u16, 1, 2, 0, 2, 3]).unwrap();
+
+        assert_eq!(mesh.vertex_count(), 4);
+        assert_eq!(mesh.triangle_count(), 2);
+        assert!((mesh.area() - 1.).abs() < 1e-6);
+        // The bounds of the triangles are padded a little, flat triangles would have empty ones.
+        let bounds = mesh.bounding_box();
+        assert!((bounds.min - Vec3::new(0., 0., 0.)).amax() < 1e-3);
+        assert!((bounds.max - Vec3::new(1., 1., 0.)).amax() < 1e-3);
+        assert_eq!(mesh.normal(0).normalize(), Vec3::new(0., 0., 1.));
+    }
+
+    #[test]
+    fn builds_meshes_without_indices() {
+        let mesh = build::<u32>(&SQUARE[..9], &[], &[], &[]).unwrap();
+
+        assert_eq!(mesh.vertex_count(), 3);
+        assert_eq!(mesh.triangle_count(), 1);
+    }
+
+    #[test]
+    fn intersects_the_triangles() {
+        let mesh = build(&SQUARE, &[], &[], &[0u32, 1, 2, 0, 2, 3]).unwrap();
+        let ray = Ray {
+            origin: Vec3::new(0.25, 0.75, 1.),
+            direction: Vec3::new(0., 0., -1.),
+        };
+
+        let hit = mesh.hit(&ray, 0.001, f32::MAX).unwrap();
+        assert_eq!(hit.triangle, 1);
+        assert!((hit.t - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rejects_invalid_position_counts() {
+        assert_eq!(build::<u32>(&SQUARE[..10], &[], &[], &[]).err(), Some(MeshError::InvalidPositionCount(10)));
+        assert_eq!(build::<u32>(&SQUARE, &[], &[], &[]).err(), Some(MeshError::InvalidVertexCount(4)));
+    }
+
+    #[test]
+    fn rejects_invalid_index_counts() {
+        assert_eq!(build(&SQUARE, &[], &[], &[0u32, 1, 2, 0]).err(), Some(MeshError::InvalidIndexCount(4)));
+    }
+
+    #[test]
+    fn rejects_indices_out_of_range() {
+        assert_eq!(
+            build(&SQUARE, &[], &[], &[0u16, 1, 2, 0, 2, 4]).err(),
+            Some(MeshError::IndexOutOfRange { index: 4, vertex_count: 4 })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_normal_counts() {
+        assert_eq!(
+            build(&SQUARE, &[0., 0., 1.], &[], &[0u32, 1, 2]).err(),
+            Some(MeshError::InvalidNormalCount { normal_count: 3, vertex_count: 4 })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_uv_counts() {
+        assert_eq!(
+            build(&SQUARE, &[], &[0., 0., 1., 0.], &[0u32, 1, 2]).err(),
+            Some(MeshError::InvalidUvCount { uv_count: 4, vertex_count: 4 })
+        );
+    }
+}
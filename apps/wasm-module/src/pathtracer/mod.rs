@@ -1,31 +1,79 @@
 use crate::pathtracer::camera::{Camera, Ray};
 use crate::pathtracer::material::{
-    DielectricMaterial, LambertianMaterial, MaterialTrait, MetalMaterial,
+    DielectricMaterial, LambertianMaterial, Material, MaterialTrait, MetalMaterial,
 };
+use crate::pathtracer::procedural::CheckerTexture;
 use crate::pathtracer::sphere::Sphere;
-use crate::pathtracer::hit::HitableList;
-use crate::pathtracer::pointlight::LightList;
+use crate::pathtracer::hit::{Hit, Hitable, HitableList, HitableShape};
+use crate::pathtracer::light::{LightList, LightSample, LightTrait};
+use crate::pathtracer::environment::{Environment, EnvironmentTrait, GradientEnvironment};
+use crate::pathtracer::mesh::{Mesh, MeshInstance, MeshRegistry};
+use crate::pathtracer::transform::Transform;
+use crate::pathtracer::aov::Aovs;
+use crate::pathtracer::filter::PixelFilter;
+use crate::pathtracer::session::FilmTile;
+use crate::pathtracer::texture::ImageRegistry;
 
 use nalgebra_glm::Vec3;
 use rand::rngs::SmallRng;
 use rand::Rng;
 use rand_core::SeedableRng;
 use nalgebra_glm::length2;
+use crate::pathtracer::math::power_heuristic;
+use std::rc::Rc;
 
+pub mod aov;
+pub mod arealight;
+pub mod bvh;
 pub mod camera;
+pub mod denoise;
+pub mod directionallight;
+pub mod distribution;
+pub mod environment;
+pub mod filter;
+pub mod hdr;
 pub mod hit;
+pub mod light;
 pub mod material;
 pub mod math;
+pub mod mesh;
 pub mod sphere;
+pub mod texture;
 pub mod triangle;
 pub mod pointlight;
+pub mod sky;
+pub mod spotlight;
+pub mod procedural;
+pub mod session;
+pub mod tonemap;
+pub mod transform;
+
+/// How the light arriving on a surface is estimated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Integrator {
+    /// The lights are sampled at every diffuse bounce, and the emissive surfaces
+    /// found by the scattered rays are ignored.
+    Naive,
+    /// Light sampling and material sampling are both kept and weighted
+    /// with the power heuristic.
+    Mis,
+}
 
 pub struct PathTracer {
     rng: SmallRng,
     pub camera: Camera,
     pub samples: u16,
+    pub integrator: Integrator,
+    /// Maximum number of surfaces a path bounces on.
+    pub max_bounces: u32,
+    /// How the samples are shared between the pixels by `render_tile`.
+    pub filter: PixelFilter,
     pub world: HitableList,
-    pub lights: LightList
+    pub lights: LightList,
+    /// Light coming from outside of the scene.
+    pub environment: Environment,
+    pub meshes: MeshRegistry,
+    pub images: ImageRegistry,
 }
 
 impl PathTracer {
@@ -34,21 +82,113 @@ impl PathTracer {
             camera,
             rng: SmallRng::seed_from_u64(0),
             samples,
+            integrator: Integrator::Mis,
+            max_bounces: 10,
+            filter: PixelFilter::default(),
             world: HitableList::new(),
-            lights: LightList::new()
+            lights: LightList::new(),
+            environment: GradientEnvironment::default().into(),
+            meshes: MeshRegistry::new(),
+            images: ImageRegistry::new(),
         }
     }
 
-    pub fn compute_pixel(&mut self, x: u32, y: u32) -> Vec3 {
-        let mut col = Vec3::new(0., 0., 0.);
-        for _ in 0..self.samples {
-            let u = (x as f32 + self.rng.gen_range(0., 1.)) / self.camera.width as f32;
-            let v = (y as f32 + self.rng.gen_range(0., 1.)) / self.camera.height as f32;
-            let ray = self.camera.get_ray(u, v);
-            col = col + color(ray, &self.world, &self.lights, &mut self.rng, 0);
+    /// Render a rectangle of the image, with `samples` samples per pixel.
+    /// The pixel filter gives each sample to the pixels around it, so the pixels around
+    /// the rectangle are sampled too: their samples reach the pixels at its border.
+    pub fn render_tile(&mut self, tile_x: u32, tile_y: u32, width: u32, height: u32) -> FilmTile {
+        // Make sure the acceleration structure matches the scene.
+        self.world.update();
+
+        let mut tile = FilmTile::new(tile_x, tile_y, width, height);
+        let margin = self.filter.margin();
+        let x_range = tile_x.saturating_sub(margin)..(tile_x + width + margin).min(self.camera.width);
+        let y_range = tile_y.saturating_sub(margin)..(tile_y + height + margin).min(self.camera.height);
+
+        for y in y_range {
+            for x in x_range.clone() {
+                let mut aovs = Aovs::empty();
+                for i in 0..self.samples {
+                    let film_x = x as f32 + self.rng.gen_range(0., 1.);
+                    let film_y = y as f32 + self.rng.gen_range(0., 1.);
+                    let u = film_x / self.camera.width as f32;
+                    let v = film_y / self.camera.height as f32;
+                    let sample = match self.camera.get_ray(u, v, &mut self.rng) {
+                        Some(ray) => trace(ray, &self.world, &self.lights, &self.environment, self.integrator, self.max_bounces, &mut self.rng),
+                        None => Aovs::empty(),
+                    };
+                    aovs.merge(&sample, i as f32, 1.);
+
+                    // Splat the sample on the pixels of the tile it reaches.
+                    let color = sample.direct + sample.indirect;
+                    let (first_x, last_x) = self.filter.pixels(film_x);
+                    let (first_y, last_y) = self.filter.pixels(film_y);
+                    for pixel_y in first_y..=last_y {
+                        for pixel_x in first_x..=last_x {
+                            if let Some(index) = tile.index(pixel_x, pixel_y) {
+                                let weight = self.filter.weight(
+                                    film_x - (pixel_x as f32 + 0.5),
+                                    film_y - (pixel_y as f32 + 0.5),
+                                );
+                                tile.sums[index] += weight * color;
+                                tile.weights[index] += weight;
+                            }
+                        }
+                    }
+                }
+
+                if let Some(index) = tile.index(x as i64, y as i64) {
+                    tile.aovs[index] = aovs;
+                }
+            }
+        }
+
+        tile
+    }
+
+    /// Add a horizontal square centered on the origin, at y = 0.
+    /// Its texture coordinates are the world X and Z coordinates, so a checker
+    /// texture of scale 1 draws squares of one unit.
+    /// The square is a single mesh instance, so that `id` can be used to edit all of it.
+    pub fn ground_plane(&mut self, id: u32, size: f32, material: Material) {
+        let half = size / 2.;
+        let positions = [
+            -half, 0., -half,
+            -half, 0., half,
+            half, 0., half,
+            half, 0., -half,
+        ];
+        let normals = [0., 1., 0., 0., 1., 0., 0., 1., 0., 0., 1., 0.];
+        let uvs = [-half, -half, -half, half, half, half, half, -half];
+        let indices: [u32; 6] = [0, 1, 2, 0, 2, 3];
+
+        if let Ok(mesh) = Mesh::new(&positions, &normals, &uvs, &indices, material) {
+            self.world.add(MeshInstance::new(id, Rc::new(mesh), Transform::identity()).into());
         }
+    }
 
-        col / self.samples as f32
+    /// Spheres in a row going away from the camera on a checkered floor,
+    /// to judge the perspective and the depth of field.
+    /// The floor gets the id `first_id`, the spheres the following ones.
+    pub fn checker_spheres(&mut self, first_id: u32) {
+        self.ground_plane(first_id, 100., LambertianMaterial {
+            albedo: CheckerTexture::new(
+                Vec3::new(0.9, 0.9, 0.9).into(),
+                Vec3::new(0.1, 0.1, 0.1).into(),
+                1.,
+            ).into(),
+        }.into());
+
+        for i in 0..5 {
+            self.world.add(Sphere::new(first_id + 1 + i,
+                Vec3::new(-2. + i as f32, 0.5, -2. * i as f32),
+                0.5,
+                LambertianMaterial {
+                    albedo: Vec3::new(0.8, 0.3, 0.1).into(),
+                }
+                .into(),
+            ).into());
+        }
     }
 
     pub fn random_spheres(&mut self) {
@@ -57,7 +197,7 @@ impl PathTracer {
             Vec3::new(0., -1000., 0.),
             1000.,
             LambertianMaterial {
-                albedo: Vec3::new(0.5, 0.5, 0.5),
+                albedo: Vec3::new(0.5, 0.5, 0.5).into(),
             }
             .into(),
         ).into());
@@ -81,7 +221,7 @@ impl PathTracer {
                                     self.rng.gen_range(0., 1.) * self.rng.gen_range(0., 1.),
                                     self.rng.gen_range(0., 1.) * self.rng.gen_range(0., 1.),
                                     self.rng.gen_range(0., 1.) * self.rng.gen_range(0., 1.),
-                                ),
+                                ).into(),
                             }
                             .into(),
                         ).into());
@@ -94,7 +234,7 @@ impl PathTracer {
                                     0.5 * (1. + self.rng.gen_range(0., 1.)),
                                     0.5 * (1. + self.rng.gen_range(0., 1.)),
                                     0.5 * (1. + self.rng.gen_range(0., 1.)),
-                                ),
+                                ).into(),
                                 fuzz: 0.5 * self.rng.gen_range(0., 1.),
                             }
                             .into(),
@@ -114,7 +254,7 @@ impl PathTracer {
             Vec3::new(-4., 1., 0.),
             1.,
             LambertianMaterial {
-                albedo: Vec3::new(0.4, 0.2, 0.1),
+                albedo: Vec3::new(0.4, 0.2, 0.1).into(),
             }
             .into(),
         ).into());
@@ -122,7 +262,7 @@ impl PathTracer {
             Vec3::new(4., 1., 0.),
             1.,
             MetalMaterial {
-                albedo: Vec3::new(0.7, 0.6, 0.5),
+                albedo: Vec3::new(0.7, 0.6, 0.5).into(),
                 fuzz: 0.,
             }
             .into(),
@@ -155,65 +295,265 @@ pub fn random_in_unit_sphere(rng: &mut SmallRng) -> Vec3 {
     p
 }
 
-/// Compute the color for a given camera ray.
-pub fn color(ray: Ray, world: &HitableList, lights: &LightList, rng: &mut SmallRng, depth: i32) -> Vec3 {
-    // Recursion lock.
-    if depth >= 10 {
-        return Vec3::new(0., 0., 0.)
-    }
+/// Random point on the unit sphere.
+pub fn random_unit_vector(rng: &mut SmallRng) -> Vec3 {
+    let z: f32 = rng.gen_range(-1., 1.);
+    let phi: f32 = rng.gen_range(0., 2. * std::f32::consts::PI);
+    let r = (1. - z * z).max(0.).sqrt();
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
 
-    // Intersect the camera ray with the scene.
-    match world.hit(&ray, 0.001, std::f32::MAX) {
-        // The ray hits something.
-        Option::Some(hit) => {
-
-            // Compute direct lighting.
-            // Pick a random light.
-            let direct_lighting = match lights.pick(rng) {
-                Some(light) => {
-                    let light_pos = &light.position;
-                    let intensity = &light.intensity;
-
-                    // Cast a shadow ray.
-                    // Check if there is an object between the light and the shading point.
-                    // todo: trace more than one ray.
-                    let shadow_ray_dir = light_pos - hit.point;
-                    let distance_squared = length2(&shadow_ray_dir);
-                    let distance = distance_squared.sqrt();
-                    let light_attenuation = 1.0 / distance_squared;
-                    let shadow_ray = Ray {
-                        origin: hit.point,
-                        direction: shadow_ray_dir / distance};
-                    if world.hit(&shadow_ray, 0.001, distance + 0.001).is_some() {
-                        0.0
-                    } else {
-                        intensity * light_attenuation
+/// Number of bounces after which paths can be terminated by Russian roulette.
+const ROULETTE_MIN_BOUNCES: u32 = 3;
+
+/// Highest probability for a path to survive the Russian roulette, so that paths
+/// through white or glass surfaces still end.
+const ROULETTE_MAX_SURVIVAL: f32 = 0.95;
+
+/// Follow the path of a camera ray. The colour is split between the direct and the
+/// indirect lighting of the outputs.
+pub fn trace(
+    ray: Ray,
+    world: &HitableList,
+    lights: &LightList,
+    environment: &Environment,
+    integrator: Integrator,
+    max_bounces: u32,
+    rng: &mut SmallRng,
+) -> Aovs {
+    let mut aovs = Aovs::empty();
+    // Radiance gathered until the direct lighting of the first surface was complete.
+    let mut direct: Option<Vec3> = None;
+    let mut radiance = Vec3::new(0., 0., 0.);
+    // Fraction of the light arriving at the current vertex that reaches the camera.
+    let mut throughput = Vec3::new(1., 1., 1.);
+    let mut ray = ray;
+    // Density of the material sampling that produced the ray, when it bounced off a
+    // diffuse surface whose direct lighting already sampled the lights.
+    // `None` for camera rays and rays reflected by specular surfaces.
+    let mut scatter_pdf: Option<f32> = None;
+
+    for bounce in 0..max_bounces {
+        // Intersect the ray with the scene.
+        let hit = match world.hit(&ray, 0.001, f32::MAX) {
+            Some(hit) => hit,
+            // The ray doesn't hit anything.
+            None => {
+                let direction = ray.direction.normalize();
+                let emitted = environment.radiance(&direction);
+                let emitted = match (scatter_pdf, integrator) {
+                    (None, _) => emitted,
+                    (Some(_), Integrator::Naive) => Vec3::new(0., 0., 0.),
+                    // A black environment is never sampled, and sends no light anyway.
+                    (Some(_), Integrator::Mis) if environment.is_black() => Vec3::new(0., 0., 0.),
+                    (Some(scatter_pdf), Integrator::Mis) => {
+                        let light_pdf = environment.pdf(&direction) / light_count(world, lights, environment) as f32;
+                        emitted * power_heuristic(scatter_pdf, light_pdf)
                     }
-                },
-                None => 0.0
-            };
+                };
+                radiance += throughput.component_mul(&emitted);
+                radiance += throughput.component_mul(
+                    &light_emission(&ray, f32::MAX, scatter_pdf, world, lights, environment, integrator));
+                break;
+            }
+        };
+        radiance += throughput.component_mul(
+            &light_emission(&ray, hit.t, scatter_pdf, world, lights, environment, integrator));
 
-            // Bounce the ray.
-            match hit.material.scatter(&ray, &hit, rng) {
-                // The material can be scattered.
-                Option::Some(scatter) => {
-                    let c = color(scatter.scattered, world, lights, rng, depth + 1);
-                    Vec3::new(
-                        direct_lighting + scatter.attenuation.x * c.x,
-                        direct_lighting + scatter.attenuation.y * c.y,
-                        direct_lighting + scatter.attenuation.z * c.z)
-                }
-                // The material cannot be scattered.
-                Option::None => {
-                    Vec3::new(0., 0., 0.)
-                }
+        let emitted = hit.material.emitted(&hit.uv, &hit.point);
+        let emitted = match (scatter_pdf, integrator) {
+            (None, _) => emitted,
+            (Some(_), Integrator::Naive) => Vec3::new(0., 0., 0.),
+            (Some(scatter_pdf), Integrator::Mis) => {
+                let light_pdf = emitter_pdf(&ray, &hit, world, lights, environment);
+                emitted * power_heuristic(scatter_pdf, light_pdf)
             }
+        };
+        radiance += throughput.component_mul(&emitted);
+
+        if bounce == 0 {
+            aovs = Aovs::from_hit(&hit, hit.t * ray.direction.magnitude());
+        } else if bounce == 1 {
+            direct = Some(radiance);
         }
-        // The ray doesn't hit anything.
-        Option::None => {
-            let unit_direction = ray.direction.normalize();
-            let t = 0.5 * (unit_direction.y + 1.);
-            (1. - t) * Vec3::new(1., 1., 1.) + (t * Vec3::new(0.5, 0.7, 1.))
+
+        // Specular surfaces can't be lit by sampling the lights: the scattered
+        // ray gathers the emission instead.
+        let specular = hit.material.is_specular();
+        if !specular {
+            radiance += throughput.component_mul(
+                &direct_lighting(&ray, &hit, world, lights, environment, integrator, rng));
         }
+
+        // Bounce the ray.
+        let scatter = match hit.material.scatter(&ray, &hit, rng) {
+            Some(scatter) => scatter,
+            None => break,
+        };
+
+        scatter_pdf = if specular {
+            None
+        } else {
+            let wo = -ray.direction.normalize();
+            let wi = scatter.scattered.direction.normalize();
+            Some(hit.material.pdf(&wo, &wi, &hit))
+        };
+        throughput = throughput.component_mul(&scatter.attenuation);
+        ray = scatter.scattered;
+
+        // Terminate the dim paths at random, and make the survivors brighter to
+        // keep the estimate unbiased.
+        if bounce + 1 >= ROULETTE_MIN_BOUNCES {
+            let survival = throughput.max().min(ROULETTE_MAX_SURVIVAL);
+            if survival <= 0. || rng.gen_range(0., 1.) >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+    }
+
+    let direct = direct.unwrap_or(radiance);
+    aovs.direct = direct;
+    aovs.indirect = radiance - direct;
+    aovs
+}
+
+/// Number of lights the direct lighting picks from: the lights, the emissive
+/// shapes and the environment, unless it is black.
+fn light_count(world: &HitableList, lights: &LightList, environment: &Environment) -> usize {
+    let environment_count = if environment.is_black() { 0 } else { 1 };
+    lights.len() + world.emitter_count() + environment_count
+}
+
+/// Compute the direct lighting at a hit point, weighted by the material BRDF.
+/// A single light is picked at random among the lights, the emissive shapes and the environment.
+fn direct_lighting(
+    ray: &Ray,
+    hit: &Hit,
+    world: &HitableList,
+    lights: &LightList,
+    environment: &Environment,
+    integrator: Integrator,
+    rng: &mut SmallRng,
+) -> Vec3 {
+    let light_count = light_count(world, lights, environment);
+    if light_count == 0 {
+        return Vec3::new(0., 0., 0.);
     }
+    let picked = rng.gen_range(0, light_count);
+    let sample = if picked < lights.len() {
+        lights.get(picked).sample(&hit.point, rng)
+    } else if picked < lights.len() + world.emitter_count() {
+        world.emitter(picked - lights.len()).and_then(|emitter| sample_emitter(emitter, hit, rng))
+    } else {
+        environment.sample(rng)
+    };
+    let sample = match sample {
+        Some(sample) => sample,
+        None => return Vec3::new(0., 0., 0.),
+    };
+
+    let wo = -ray.direction.normalize();
+    let brdf = hit.material.eval(&wo, &sample.direction, hit);
+    let cos_surface = sample.direction.dot(&hit.normal).abs();
+    if brdf == Vec3::new(0., 0., 0.) || cos_surface <= 0. {
+        return Vec3::new(0., 0., 0.);
+    }
+
+    // Cast a shadow ray.
+    // Check if there is an object between the light and the shading point.
+    let shadow_ray = Ray {
+        origin: hit.point,
+        direction: sample.direction};
+    if world.occluded(&shadow_ray, 0.001, sample.distance - 0.001) {
+        return Vec3::new(0., 0., 0.);
+    }
+
+    // Only one light out of `light_count` was sampled.
+    let weight = match sample.pdf {
+        None => light_count as f32,
+        Some(pdf) => {
+            let light_pdf = pdf / light_count as f32;
+            let mis_weight = match integrator {
+                Integrator::Naive => 1.,
+                Integrator::Mis => power_heuristic(light_pdf, hit.material.pdf(&wo, &sample.direction, hit)),
+            };
+            mis_weight / light_pdf
+        }
+    };
+
+    brdf.component_mul(&sample.radiance) * cos_surface * weight
+}
+
+/// Pick a point on an emissive shape.
+fn sample_emitter(emitter: &HitableShape, hit: &Hit, rng: &mut SmallRng) -> Option<LightSample> {
+    let sample = emitter.sample_surface(rng);
+    let shadow_ray_dir = sample.point - hit.point;
+    let distance_squared = length2(&shadow_ray_dir);
+    let distance = distance_squared.sqrt();
+    if sample.pdf <= 0. || distance <= 0. {
+        return None;
+    }
+
+    let direction = shadow_ray_dir / distance;
+    let cos_light = sample.normal.dot(&direction).abs();
+    if cos_light <= 0. {
+        return None;
+    }
+
+    Some(LightSample {
+        direction,
+        distance,
+        radiance: emitter.material().emitted(&sample.uv, &sample.point),
+        pdf: Some(area_to_solid_angle(sample.pdf, distance_squared, cos_light)),
+    })
+}
+
+/// Radiance of the lights hit by a ray before `t_max`, weighted like the emission
+/// of the emissive surfaces.
+fn light_emission(
+    ray: &Ray,
+    t_max: f32,
+    scatter_pdf: Option<f32>,
+    world: &HitableList,
+    lights: &LightList,
+    environment: &Environment,
+    integrator: Integrator,
+) -> Vec3 {
+    let light_count = light_count(world, lights, environment);
+    let direction = ray.direction.normalize();
+
+    lights
+        .iter()
+        .filter_map(|light| {
+            let emitted = light.hit(ray, t_max)?;
+            Some(match (scatter_pdf, integrator) {
+                (None, _) => emitted,
+                (Some(_), Integrator::Naive) => Vec3::new(0., 0., 0.),
+                (Some(scatter_pdf), Integrator::Mis) => {
+                    let light_pdf = light.pdf(&ray.origin, &direction) / light_count as f32;
+                    emitted * power_heuristic(scatter_pdf, light_pdf)
+                }
+            })
+        })
+        .fold(Vec3::new(0., 0., 0.), |sum, emitted| sum + emitted)
+}
+
+/// Density with which the direct lighting would have sampled the emissive surface hit by a ray,
+/// per unit of solid angle. Like `sample_surface`, it uses the geometric normal of the surface.
+fn emitter_pdf(ray: &Ray, hit: &Hit, world: &HitableList, lights: &LightList, environment: &Environment) -> f32 {
+    let light_count = light_count(world, lights, environment);
+    let to_light = hit.point - ray.origin;
+    let cos_light = hit.geometric_normal.dot(&to_light.normalize()).abs();
+    if hit.surface_pdf <= 0. || cos_light <= 0. {
+        return 0.;
+    }
+
+    area_to_solid_angle(hit.surface_pdf, length2(&to_light), cos_light) / light_count as f32
+}
+
+/// Convert a density per unit of area into a density per unit of solid angle,
+/// as seen from a point at the given squared distance.
+fn area_to_solid_angle(pdf: f32, distance_squared: f32, cos_light: f32) -> f32 {
+    pdf * distance_squared / cos_light
 }
@@ -1,15 +1,18 @@
-use nalgebra_glm::Vec3;
+use crate::pathtracer::light::{LightSample, LightTrait};
+use crate::pathtracer::math::kelvin_to_rgb;
+use nalgebra_glm::{length2, Vec3};
 use rand::rngs::SmallRng;
-use rand::Rng;
 
+/// Light emitted equally in every direction from a point.
 pub struct PointLight {
     id: u32,
-    pub intensity: f32,
+    /// Radiant intensity of each RGB channel.
+    pub intensity: Vec3,
     pub position: Vec3,
 }
 
 impl PointLight {
-    pub fn new(id: u32, position: Vec3, intensity: f32) -> PointLight {
+    pub fn new(id: u32, position: Vec3, intensity: Vec3) -> PointLight {
         PointLight {
             id,
             intensity,
@@ -17,40 +20,30 @@ impl PointLight {
         }
     }
 
-    fn id(&self) -> u32 {
-        self.id
+    /// Light with the colour of a black body at the given temperature, in Kelvin.
+    pub fn from_temperature(id: u32, position: Vec3, kelvin: f32, intensity: f32) -> PointLight {
+        PointLight::new(id, position, intensity * kelvin_to_rgb(kelvin))
     }
 }
 
-pub struct LightList {
-    list: Vec<PointLight>,
-}
-
-impl LightList {
-    pub fn new() -> LightList {
-        LightList {
-            list: Vec::<PointLight>::new(),
-        }
-    }
-
-    pub fn add(&mut self, light: PointLight) {
-        self.list.push(light);
-    }
-
-    pub fn find(&mut self, id: u32) -> Option<&mut PointLight> {
-        self.list.iter_mut().find(|light| light.id() == id)
-    }
-
-    pub fn remove(&mut self, id: u32) {
-        self.list.retain(|light| light.id() != id);
+impl LightTrait for PointLight {
+    fn id(&self) -> u32 {
+        self.id
     }
 
-    pub fn pick(&self, rng: &mut SmallRng) -> Option<&PointLight> {
-        if self.list.len() > 0 {
-            let i = rng.gen_range(0, self.list.len());
-            Some(&self.list[i])
-        } else {
-            None
+    fn sample(&self, point: &Vec3, _rng: &mut SmallRng) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance_squared = length2(&to_light);
+        let distance = distance_squared.sqrt();
+        if distance <= 0. {
+            return None;
         }
+
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            radiance: self.intensity / distance_squared,
+            pdf: None,
+        })
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,205 @@
+use crate::pathtracer::math::clamp;
+use crate::pathtracer::texture::{Texture, TextureTrait};
+use nalgebra_glm::{Vec2, Vec3};
+use rand::rngs::SmallRng;
+use rand::Rng;
+use rand_core::SeedableRng;
+use std::rc::Rc;
+
+/// Number of gradients in the Perlin lattice. Must be a power of two.
+const PERLIN_SIZE: usize = 256;
+
+/// Alternates between two textures on a grid in texture space.
+#[derive(Clone)]
+pub struct CheckerTexture {
+    pub even: Rc<Texture>,
+    pub odd: Rc<Texture>,
+    /// Number of squares per texture coordinate unit.
+    pub scale: f32,
+}
+
+impl CheckerTexture {
+    pub fn new(even: Texture, odd: Texture, scale: f32) -> CheckerTexture {
+        CheckerTexture {
+            even: Rc::new(even),
+            odd: Rc::new(odd),
+            scale,
+        }
+    }
+}
+
+impl TextureTrait for CheckerTexture {
+    fn value(&self, uv: &Vec2, point: &Vec3) -> Vec3 {
+        let cell = (uv.x * self.scale).floor() as i64 + (uv.y * self.scale).floor() as i64;
+        if cell.rem_euclid(2) == 0 {
+            self.even.value(uv, point)
+        } else {
+            self.odd.value(uv, point)
+        }
+    }
+}
+
+/// Linear blend between two colours along a segment in world space.
+/// Points before `from` get `start`, points after `to` get `end`.
+#[derive(Clone)]
+pub struct GradientTexture {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub from: Vec3,
+    pub to: Vec3,
+}
+
+impl TextureTrait for GradientTexture {
+    fn value(&self, _uv: &Vec2, point: &Vec3) -> Vec3 {
+        let axis = self.to - self.from;
+        let length_squared = axis.magnitude_squared();
+        let t = if length_squared > 0.0 {
+            clamp((point - self.from).dot(&axis) / length_squared, 0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        (1.0 - t) * self.start + t * self.end
+    }
+}
+
+/// Perlin gradient noise.
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    permutation_x: Vec<usize>,
+    permutation_y: Vec<usize>,
+    permutation_z: Vec<usize>,
+}
+
+impl Perlin {
+    /// The same seed always gives the same noise, so renders are reproducible.
+    pub fn new(seed: u64) -> Perlin {
+        let mut rng = SmallRng::seed_from_u64(seed);
+
+        let gradients = (0..PERLIN_SIZE)
+            .map(|_| {
+                Vec3::new(
+                    rng.gen_range(-1., 1.),
+                    rng.gen_range(-1., 1.),
+                    rng.gen_range(-1., 1.),
+                )
+                .normalize()
+            })
+            .collect();
+
+        Perlin {
+            gradients,
+            permutation_x: permutation(&mut rng),
+            permutation_y: permutation(&mut rng),
+            permutation_z: permutation(&mut rng),
+        }
+    }
+
+    /// Noise value in [-1, 1].
+    pub fn noise(&self, point: &Vec3) -> f32 {
+        let floor = Vec3::new(point.x.floor(), point.y.floor(), point.z.floor());
+        let fraction = point - floor;
+
+        let mask = PERLIN_SIZE as i64 - 1;
+        let i = floor.x as i64;
+        let j = floor.y as i64;
+        let k = floor.z as i64;
+
+        // Hermite smoothing of the interpolation weights.
+        let u = fraction.map(|f| f * f * (3.0 - 2.0 * f));
+
+        let mut accumulated = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let gradient = &self.gradients[self.permutation_x[((i + di) & mask) as usize]
+                        ^ self.permutation_y[((j + dj) & mask) as usize]
+                        ^ self.permutation_z[((k + dk) & mask) as usize]];
+
+                    let (di, dj, dk) = (di as f32, dj as f32, dk as f32);
+                    let weight = fraction - Vec3::new(di, dj, dk);
+                    accumulated += (di * u.x + (1.0 - di) * (1.0 - u.x))
+                        * (dj * u.y + (1.0 - dj) * (1.0 - u.y))
+                        * (dk * u.z + (1.0 - dk) * (1.0 - u.z))
+                        * gradient.dot(&weight);
+                }
+            }
+        }
+
+        accumulated
+    }
+
+    /// Sum of the noise over several octaves, in [0, 1[ for most points.
+    pub fn turbulence(&self, point: &Vec3, octaves: u32) -> f32 {
+        let mut accumulated = 0.0;
+        let mut point = *point;
+        let mut weight = 1.0;
+
+        for _ in 0..octaves {
+            accumulated += weight * self.noise(&point);
+            weight *= 0.5;
+            point *= 2.0;
+        }
+
+        accumulated.abs()
+    }
+}
+
+fn permutation(rng: &mut SmallRng) -> Vec<usize> {
+    let mut permutation: Vec<usize> = (0..PERLIN_SIZE).collect();
+    for i in (1..PERLIN_SIZE).rev() {
+        let target = rng.gen_range(0, i + 1);
+        permutation.swap(i, target);
+    }
+    permutation
+}
+
+/// Smooth noise between black and `color`.
+#[derive(Clone)]
+pub struct NoiseTexture {
+    pub perlin: Rc<Perlin>,
+    pub color: Vec3,
+    /// Frequency of the noise in world space.
+    pub scale: f32,
+}
+
+impl TextureTrait for NoiseTexture {
+    fn value(&self, _uv: &Vec2, point: &Vec3) -> Vec3 {
+        0.5 * (1.0 + self.perlin.noise(&(self.scale * point))) * self.color
+    }
+}
+
+/// Turbulent noise between black and `color`.
+#[derive(Clone)]
+pub struct TurbulenceTexture {
+    pub perlin: Rc<Perlin>,
+    pub color: Vec3,
+    pub scale: f32,
+    pub octaves: u32,
+}
+
+impl TextureTrait for TurbulenceTexture {
+    fn value(&self, _uv: &Vec2, point: &Vec3) -> Vec3 {
+        self.perlin
+            .turbulence(&(self.scale * point), self.octaves)
+            .min(1.0)
+            * self.color
+    }
+}
+
+/// Veins along the Z axis, perturbed by turbulence.
+#[derive(Clone)]
+pub struct MarbleTexture {
+    pub perlin: Rc<Perlin>,
+    pub color: Vec3,
+    pub scale: f32,
+    /// How much the turbulence bends the veins.
+    pub turbulence: f32,
+}
+
+impl TextureTrait for MarbleTexture {
+    fn value(&self, _uv: &Vec2, point: &Vec3) -> Vec3 {
+        let phase = self.scale * point.z + self.turbulence * self.perlin.turbulence(point, 7);
+        0.5 * (1.0 + phase.sin()) * self.color
+    }
+}
@@ -0,0 +1,210 @@
+use crate::pathtracer::aov::Aovs;
+use nalgebra_glm::Vec3;
+
+/// Running sum of the samples of every pixel of the image, so that each render
+/// refines the previous ones instead of starting over.
+/// The pixels are stored row by row, starting from the bottom row like the camera.
+pub struct RenderSession {
+    width: u32,
+    height: u32,
+    /// Version of the scene the samples were taken from.
+    version: u64,
+    sums: Vec<Vec3>,
+    weights: Vec<f32>,
+    /// Whether the auxiliary outputs are kept. They take most of the memory of the session.
+    keep_aovs: bool,
+    /// Auxiliary outputs, averaged over `samples` samples. Empty unless they are kept.
+    aovs: Vec<Aovs>,
+    samples: Vec<f32>,
+}
+
+impl Default for RenderSession {
+    fn default() -> Self {
+        RenderSession::new()
+    }
+}
+
+impl RenderSession {
+    pub fn new() -> RenderSession {
+        RenderSession {
+            width: 0,
+            height: 0,
+            version: 0,
+            sums: Vec::new(),
+            weights: Vec::new(),
+            keep_aovs: false,
+            aovs: Vec::new(),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Keep the auxiliary outputs of the samples, or free them.
+    /// Starting to keep them forgets all the samples, so that every output covers the same samples.
+    pub fn keep_aovs(&mut self, keep: bool) {
+        if keep == self.keep_aovs {
+            return;
+        }
+
+        self.keep_aovs = keep;
+        if keep {
+            self.aovs = vec![Aovs::empty(); (self.width * self.height) as usize];
+            self.reset();
+        } else {
+            self.aovs = Vec::new();
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Forget all the samples.
+    pub fn reset(&mut self) {
+        for sum in self.sums.iter_mut() {
+            *sum = Vec3::new(0., 0., 0.);
+        }
+        for weight in self.weights.iter_mut() {
+            *weight = 0.;
+        }
+        for aovs in self.aovs.iter_mut() {
+            *aovs = Aovs::empty();
+        }
+        for samples in self.samples.iter_mut() {
+            *samples = 0.;
+        }
+    }
+
+    /// Start over when the image size or the version of the scene changed.
+    /// Returns whether the samples were kept.
+    pub fn sync(&mut self, width: u32, height: u32, version: u64) -> bool {
+        if width != self.width || height != self.height {
+            let size = (width * height) as usize;
+            self.width = width;
+            self.height = height;
+            self.version = version;
+            self.sums = vec![Vec3::new(0., 0., 0.); size];
+            self.weights = vec![0.; size];
+            self.aovs = if self.keep_aovs { vec![Aovs::empty(); size] } else { Vec::new() };
+            self.samples = vec![0.; size];
+            return false;
+        }
+
+        if version != self.version {
+            self.version = version;
+            self.reset();
+            return false;
+        }
+
+        true
+    }
+
+    /// Add samples to a pixel, given by the sum of their colours multiplied by their
+    /// weights, and the sum of their weights. Pixels outside of the image are ignored.
+    pub fn add(&mut self, x: u32, y: u32, sum: Vec3, weight: f32) {
+        if let Some(index) = self.index(x, y) {
+            self.sums[index] += sum;
+            self.weights[index] += weight;
+        }
+    }
+
+    /// Replace the samples of a pixel, given like in `add`.
+    pub fn set(&mut self, x: u32, y: u32, sum: Vec3, weight: f32) {
+        if let Some(index) = self.index(x, y) {
+            self.sums[index] = sum;
+            self.weights[index] = weight;
+        }
+    }
+
+    /// Add the auxiliary outputs of `samples` samples to a pixel.
+    /// Only the number of samples is updated when the outputs are not kept.
+    pub fn add_aovs(&mut self, x: u32, y: u32, aovs: &Aovs, samples: f32) {
+        if let Some(index) = self.index(x, y) {
+            if let Some(pixel) = self.aovs.get_mut(index) {
+                pixel.merge(aovs, self.samples[index], samples);
+            }
+            self.samples[index] += samples;
+        }
+    }
+
+    /// Replace the auxiliary outputs of a pixel by the ones of `samples` samples.
+    pub fn set_aovs(&mut self, x: u32, y: u32, aovs: &Aovs, samples: f32) {
+        if let Some(index) = self.index(x, y) {
+            if let Some(pixel) = self.aovs.get_mut(index) {
+                *pixel = *aovs;
+            }
+            self.samples[index] = samples;
+        }
+    }
+
+    /// Auxiliary outputs of a pixel, empty when it has no samples or they are not kept.
+    pub fn aovs(&self, x: u32, y: u32) -> Aovs {
+        self.index(x, y)
+            .and_then(|index| self.aovs.get(index))
+            .map_or(Aovs::empty(), |aovs| *aovs)
+    }
+
+    /// Number of samples of a pixel.
+    pub fn samples(&self, x: u32, y: u32) -> u32 {
+        self.index(x, y).map_or(0, |index| self.samples[index] as u32)
+    }
+
+    /// Average of the samples of a pixel, black when their weights don't add up to
+    /// anything positive.
+    pub fn average(&self, x: u32, y: u32) -> Vec3 {
+        match self.index(x, y) {
+            Some(index) if self.weights[index] > 0. => self.sums[index] / self.weights[index],
+            _ => Vec3::new(0., 0., 0.),
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some((y * self.width + x) as usize)
+        } else {
+            None
+        }
+    }
+}
+
+/// Samples of a rectangle of the image, weighted by the pixel filter.
+/// The pixels are stored row by row, starting from the bottom row.
+pub struct FilmTile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Sum of the colours of the samples multiplied by their weights.
+    pub sums: Vec<Vec3>,
+    pub weights: Vec<f32>,
+    /// Auxiliary outputs of the samples taken in each pixel, without any filter.
+    pub aovs: Vec<Aovs>,
+}
+
+impl FilmTile {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> FilmTile {
+        let size = (width * height) as usize;
+        FilmTile {
+            x,
+            y,
+            width,
+            height,
+            sums: vec![Vec3::new(0., 0., 0.); size],
+            weights: vec![0.; size],
+            aovs: vec![Aovs::empty(); size],
+        }
+    }
+
+    /// Index of a pixel given in image coordinates, if it is in the tile.
+    pub fn index(&self, x: i64, y: i64) -> Option<usize> {
+        let (x, y) = (x - self.x as i64, y - self.y as i64);
+        if x >= 0 && y >= 0 && x < self.width as i64 && y < self.height as i64 {
+            Some((y * self.width as i64 + x) as usize)
+        } else {
+            None
+        }
+    }
+}
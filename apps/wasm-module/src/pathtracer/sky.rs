@@ -0,0 +1,235 @@
+use crate::pathtracer::environment::EnvironmentTrait;
+use crate::pathtracer::math::{clamp, smoothstep};
+use nalgebra_glm::{pi, Vec3};
+
+/// Illuminance of the sun above the atmosphere, in kilolux.
+const SOLAR_ILLUMINANCE: f32 = 128.;
+
+/// Apparent diameter of the sun, in radians.
+pub const SUN_ANGULAR_DIAMETER: f32 = 0.0093;
+
+/// Elevation of the sun, in radians, at which the sky has faded to black: the end of civil twilight.
+const TWILIGHT_ELEVATION: f32 = -0.1047;
+
+/// Wavelengths of the red, green and blue channels, in micrometers.
+const WAVELENGTHS: [f32; 3] = [0.680, 0.550, 0.440];
+
+/// Coefficients of the Perez distribution of one sky quantity.
+#[derive(Clone, Copy)]
+struct Perez {
+    a: f32,
+    b: f32,
+    c: f32,
+    d: f32,
+    e: f32,
+}
+
+impl Perez {
+    /// Relative value at the zenith angle `theta` and the angle `gamma` from the sun.
+    fn value(&self, cos_theta: f32, gamma: f32) -> f32 {
+        let cos_gamma = gamma.cos();
+        (1. + self.a * (self.b / cos_theta).exp())
+            * (1. + self.c * (self.d * gamma).exp() + self.e * cos_gamma * cos_gamma)
+    }
+}
+
+/// Daylight sky of Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight".
+///
+/// Radiances are in kcd/m², multiplied by `intensity`. The sun itself is not part of the
+/// sky: it is a directional light, see `sun_irradiance`.
+#[derive(Clone)]
+pub struct SkyEnvironment {
+    /// Unit vector towards the sun.
+    sun_direction: Vec3,
+    luminance: Perez,
+    chromaticity_x: Perez,
+    chromaticity_y: Perez,
+    /// Luminance and chromaticity at the zenith, divided by the Perez value there.
+    zenith: Vec3,
+    /// Fraction of the sky light left at dusk, one when the sun is above the horizon.
+    twilight: f32,
+    pub intensity: f32,
+}
+
+impl SkyEnvironment {
+    /// `turbidity` goes from 2 for a clear sky to 10 for a hazy one.
+    /// The model doesn't cover a sun below the horizon: the sky of a sun on the horizon
+    /// is used instead, fading to black at the end of the civil twilight.
+    pub fn new(sun_direction: Vec3, turbidity: f32, intensity: f32) -> SkyEnvironment {
+        let t = turbidity.max(1.);
+        let elevation = clamp(sun_direction.normalize().y, -1., 1.).asin();
+        let twilight = smoothstep(TWILIGHT_ELEVATION, 0., elevation);
+        let sun_direction = above_horizon(&sun_direction);
+        let theta_sun = sun_direction.y.min(1.).acos();
+
+        let luminance = Perez {
+            a: 0.1787 * t - 1.4630,
+            b: -0.3554 * t + 0.4275,
+            c: -0.0227 * t + 5.3251,
+            d: 0.1206 * t - 2.5771,
+            e: -0.0670 * t + 0.3703,
+        };
+        let chromaticity_x = Perez {
+            a: -0.0193 * t - 0.2592,
+            b: -0.0665 * t + 0.0008,
+            c: -0.0004 * t + 0.2125,
+            d: -0.0641 * t - 0.8989,
+            e: -0.0033 * t + 0.0452,
+        };
+        let chromaticity_y = Perez {
+            a: -0.0167 * t - 0.2608,
+            b: -0.0950 * t + 0.0092,
+            c: -0.0079 * t + 0.2102,
+            d: -0.0441 * t - 1.6537,
+            e: -0.0109 * t + 0.0529,
+        };
+
+        let chi = (4. / 9. - t / 120.) * (pi::<f32>() - 2. * theta_sun);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let theta = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.];
+        let polynomial = |coefficients: [[f32; 4]; 3]| -> f32 {
+            let factors = [t * t, t, 1.];
+            (0..3)
+                .map(|i| factors[i] * (0..4).map(|j| coefficients[i][j] * theta[j]).sum::<f32>())
+                .sum()
+        };
+        let zenith_x = polynomial([
+            [0.00166, -0.00375, 0.00209, 0.],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = polynomial([
+            [0.00275, -0.00610, 0.00317, 0.],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        SkyEnvironment {
+            sun_direction,
+            luminance,
+            chromaticity_x,
+            chromaticity_y,
+            zenith: Vec3::new(
+                zenith_luminance / luminance.value(1., theta_sun),
+                zenith_x / chromaticity_x.value(1., theta_sun),
+                zenith_y / chromaticity_y.value(1., theta_sun),
+            ),
+            twilight,
+            intensity,
+        }
+    }
+}
+
+impl EnvironmentTrait for SkyEnvironment {
+    /// Below the horizon, the sky keeps its colour at the horizon.
+    fn radiance(&self, direction: &Vec3) -> Vec3 {
+        let direction = above_horizon(direction);
+        let cos_theta = direction.y.max(0.01);
+        let gamma = clamp(direction.dot(&self.sun_direction), -1., 1.).acos();
+
+        let luminance = self.zenith.x * self.luminance.value(cos_theta, gamma);
+        let x = self.zenith.y * self.chromaticity_x.value(cos_theta, gamma);
+        let y = self.zenith.z * self.chromaticity_y.value(cos_theta, gamma);
+
+        self.intensity * self.twilight * xyy_to_rgb(x, y, luminance.max(0.))
+    }
+
+    fn is_black(&self) -> bool {
+        self.intensity * self.twilight <= 0.
+    }
+}
+
+/// Irradiance of the sun on a surface facing it, in kilolux multiplied by `intensity`,
+/// after its light crossed the atmosphere. Zero when the sun is below the horizon.
+pub fn sun_irradiance(sun_direction: &Vec3, turbidity: f32, intensity: f32) -> Vec3 {
+    let elevation = clamp(sun_direction.normalize().y, -1., 1.).asin();
+    if elevation <= 0. {
+        return Vec3::new(0., 0., 0.);
+    }
+
+    // Relative optical air mass, Kasten and Young.
+    let zenith_degrees = 90. - elevation.to_degrees();
+    let air_mass = 1. / (zenith_degrees.to_radians().cos() + 0.50572 * (96.07995 - zenith_degrees).powf(-1.6364));
+
+    // Rayleigh scattering and the Ångström law for aerosols.
+    let beta = 0.04608 * turbidity.max(1.) - 0.04586;
+    let transmittance = |wavelength: f32| {
+        let rayleigh = 0.008735 * wavelength.powf(-4.08);
+        let aerosol = beta * wavelength.powf(-1.3);
+        (-air_mass * (rayleigh + aerosol)).exp()
+    };
+
+    intensity
+        * SOLAR_ILLUMINANCE
+        * Vec3::new(
+            transmittance(WAVELENGTHS[0]),
+            transmittance(WAVELENGTHS[1]),
+            transmittance(WAVELENGTHS[2]),
+        )
+}
+
+/// Unit vector towards the sun, from its elevation above the horizon and its azimuth,
+/// both in radians. The azimuth goes from the north (-Z) to the east (+X).
+pub fn sun_direction(elevation: f32, azimuth: f32) -> Vec3 {
+    Vec3::new(
+        elevation.cos() * azimuth.sin(),
+        elevation.sin(),
+        -elevation.cos() * azimuth.cos(),
+    )
+}
+
+/// Elevation and azimuth of the sun, in radians, on a day of a non leap year at a
+/// local solar time in hours and a latitude in degrees.
+pub fn solar_position(month: u32, day: u32, hour: f32, latitude: f32) -> (f32, f32) {
+    const DAYS_BEFORE_MONTH: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let day_of_year = DAYS_BEFORE_MONTH[(month.clamp(1, 12) - 1) as usize] + day.max(1);
+
+    let declination = (23.44f32).to_radians()
+        * (2. * pi::<f32>() * (284. + day_of_year as f32) / 365.).sin();
+    let hour_angle = (15. * (hour - 12.)).to_radians();
+    let latitude = clamp(latitude, -90., 90.).to_radians();
+
+    let elevation = clamp(
+        latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos(),
+        -1.,
+        1.,
+    )
+    .asin();
+    let azimuth = hour_angle.sin().atan2(
+        hour_angle.cos() * latitude.sin() - declination.tan() * latitude.cos(),
+    ) + pi::<f32>();
+
+    (elevation, azimuth)
+}
+
+/// The unit vector, lowered to the horizon when it points below it.
+fn above_horizon(direction: &Vec3) -> Vec3 {
+    if direction.y >= 0. {
+        return direction.normalize();
+    }
+
+    let horizontal = Vec3::new(direction.x, 0., direction.z);
+    if horizontal.magnitude_squared() > 0. {
+        horizontal.normalize()
+    } else {
+        // Straight down: any direction on the horizon will do.
+        Vec3::new(0., 0., 1.)
+    }
+}
+
+/// Linear sRGB colour of a CIE xyY colour.
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Vec3 {
+    if y <= 0. {
+        return Vec3::new(0., 0., 0.);
+    }
+
+    let big_x = x / y * luminance;
+    let big_z = (1. - x - y) / y * luminance;
+    Vec3::new(
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+    )
+    .map(|channel| channel.max(0.))
+}
@@ -1,13 +1,22 @@
 use crate::pathtracer::camera::Ray;
-use crate::pathtracer::material::Material;
-use crate::pathtracer::hit::{Hitable, Hit};
-use nalgebra_glm::Vec3;
+use crate::pathtracer::material::{Material, MaterialTrait};
+use crate::pathtracer::hit::{Hitable, Hit, SurfaceSample};
+use crate::pathtracer::random_in_unit_sphere;
+use crate::pathtracer::bvh::Aabb;
+use crate::pathtracer::transform::Transform;
+use crate::pathtracer::math::clamp;
+use nalgebra_glm::{pi, two_pi, Vec2, Vec3};
+use rand::rngs::SmallRng;
 
 pub struct Sphere {
     id: u32,
     pub center: Vec3,
     pub radius: f32,
     pub material: Material,
+    /// Id of the material, for the material id pass.
+    pub material_id: u32,
+    /// Applied on top of the center and radius, to get ellipsoids.
+    pub transform: Transform,
 }
 
 impl Sphere {
@@ -17,12 +26,22 @@ impl Sphere {
             center,
             radius,
             material,
+            material_id: 0,
+            transform: Transform::identity(),
         }
     }
+
+    /// Density of a uniform sample around the point with the given object space normal,
+    /// per unit of world area.
+    fn surface_pdf(&self, local_normal: &Vec3) -> f32 {
+        let area = 4. * pi::<f32>() * self.radius * self.radius;
+        1. / (area * self.transform.area_scale(local_normal))
+    }
 }
 
 impl Hitable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+    fn hit(&self, world_ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+        let ray = &self.transform.ray_to_local(world_ray);
         let oc: Vec3 = ray.origin - self.center;
         let a: f32 = ray.direction.dot(&ray.direction);
         let b: f32 = ray.direction.dot(&oc);
@@ -30,27 +49,40 @@ impl Hitable for Sphere {
         let discriminant: f32 = (b * b) - (a * c);
 
         if discriminant > 0. {
+            let emissive = self.material.is_emissive();
             let rooted_discriminant = discriminant.sqrt();
             let t: f32 = (-b - rooted_discriminant) / a;
 
             let point = ray.point_at_parameter(t);
             if t < t_max && t > t_min {
+                let normal = self.transform.normal_to_world(&((point - self.center) / self.radius));
                 return Some(Hit {
-                    t: t,
-                    point,
-                    normal: ((point - self.center) / self.radius).normalize(),
+                    t,
+                    point: world_ray.point_at_parameter(t),
+                    normal,
+                    geometric_normal: normal,
+                    uv: sphere_uv(&((point - self.center) / self.radius)),
                     material: self.material.clone(),
+                    material_id: self.material_id,
+                    object_id: self.id,
+                    surface_pdf: if emissive { self.surface_pdf(&((point - self.center) / self.radius)) } else { 0. },
                 });
             }
 
             let temp: f32 = (-b + rooted_discriminant) / a;
             if temp < t_max && temp > t_min {
                 let point = ray.point_at_parameter(temp);
+                let normal = self.transform.normal_to_world(&((point - self.center) / self.radius));
                 return Some(Hit {
                     t: temp,
-                    point,
-                    normal: ((point - self.center) / self.radius).normalize(),
+                    point: world_ray.point_at_parameter(temp),
+                    normal,
+                    geometric_normal: normal,
+                    uv: sphere_uv(&((point - self.center) / self.radius)),
                     material: self.material.clone(),
+                    material_id: self.material_id,
+                    object_id: self.id,
+                    surface_pdf: if emissive { self.surface_pdf(&((point - self.center) / self.radius)) } else { 0. },
                 });
             }
         }
@@ -61,4 +93,45 @@ impl Hitable for Sphere {
     fn id(&self) -> u32 {
         self.id
     }
+
+    fn bounding_box(&self) -> Aabb {
+        let radius = Vec3::new(self.radius, self.radius, self.radius);
+        self.transform.aabb_to_world(&Aabb::new(self.center - radius, self.center + radius))
+    }
+
+    fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn sample_surface(&self, rng: &mut SmallRng) -> SurfaceSample {
+        // Uniform direction from the center.
+        let mut direction = random_in_unit_sphere(rng);
+        while direction.magnitude_squared() < 0.0001 {
+            direction = random_in_unit_sphere(rng);
+        }
+        let local_normal = direction.normalize();
+
+        SurfaceSample {
+            point: self.transform.point_to_world(&(self.center + self.radius * local_normal)),
+            normal: self.transform.normal_to_world(&local_normal),
+            uv: sphere_uv(&local_normal),
+            pdf: self.surface_pdf(&local_normal),
+        }
+    }
+}
+
+/// Equirectangular texture coordinates of a point on the unit sphere.
+/// `u` goes around the Y axis starting from -X, `v` goes from the top pole to the bottom one,
+/// like the rows of an image.
+pub fn sphere_uv(point: &Vec3) -> Vec2 {
+    let phi = point.z.atan2(-point.x);
+    let theta = clamp(point.y, -1.0, 1.0).asin();
+    Vec2::new(
+        0.5 + phi / two_pi::<f32>(),
+        0.5 - theta / std::f32::consts::PI,
+    )
 }
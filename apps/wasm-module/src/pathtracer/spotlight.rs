@@ -0,0 +1,86 @@
+use crate::pathtracer::light::{LightSample, LightTrait};
+use crate::pathtracer::math::smoothstep;
+use nalgebra_glm::{length2, Vec3};
+use rand::rngs::SmallRng;
+
+/// Point light restricted to a cone.
+/// The intensity is full inside the inner cone and fades out up to the outer cone.
+pub struct SpotLight {
+    id: u32,
+    pub position: Vec3,
+    /// Unit vector along the axis of the cone, pointing away from the light.
+    pub direction: Vec3,
+    /// Radiant intensity of each RGB channel on the axis.
+    pub intensity: Vec3,
+    /// Cosine of the half angle of the inner cone.
+    pub cos_inner: f32,
+    /// Cosine of the half angle of the outer cone.
+    pub cos_outer: f32,
+}
+
+impl SpotLight {
+    /// The angles are the half angles of the cones, in radians.
+    /// A zero direction gives a light that emits nothing.
+    pub fn new(
+        id: u32,
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> SpotLight {
+        let outer_angle = outer_angle.max(0.);
+        let inner_angle = inner_angle.max(0.).min(outer_angle);
+        let length = direction.magnitude();
+        SpotLight {
+            id,
+            position,
+            direction: if length > 0. { direction / length } else { Vec3::new(0., 0., 0.) },
+            intensity,
+            cos_inner: inner_angle.cos(),
+            cos_outer: outer_angle.cos(),
+        }
+    }
+
+    /// Fraction of the intensity sent along the unit vector `direction`.
+    fn falloff(&self, direction: &Vec3) -> f32 {
+        let cosine = self.direction.dot(direction);
+        if self.cos_inner <= self.cos_outer {
+            return if cosine >= self.cos_outer { 1. } else { 0. };
+        }
+
+        smoothstep(self.cos_outer, self.cos_inner, cosine)
+    }
+}
+
+impl LightTrait for SpotLight {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn sample(&self, point: &Vec3, _rng: &mut SmallRng) -> Option<LightSample> {
+        if self.direction == Vec3::new(0., 0., 0.) {
+            return None;
+        }
+
+        let to_light = self.position - point;
+        let distance_squared = length2(&to_light);
+        let distance = distance_squared.sqrt();
+        if distance <= 0. {
+            return None;
+        }
+
+        let direction = to_light / distance;
+        let falloff = self.falloff(&-direction);
+        if falloff <= 0. {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: falloff * self.intensity / distance_squared,
+            pdf: None,
+        })
+    }
+}
@@ -0,0 +1,243 @@
+use crate::pathtracer::procedural::{
+    CheckerTexture, GradientTexture, MarbleTexture, NoiseTexture, TurbulenceTexture,
+};
+use enum_dispatch::enum_dispatch;
+use nalgebra_glm::{Vec2, Vec3};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// A colour that varies over a surface.
+#[enum_dispatch(Texture)]
+pub trait TextureTrait {
+    /// Colour at the given texture coordinates and world position.
+    fn value(&self, uv: &Vec2, point: &Vec3) -> Vec3;
+}
+
+#[enum_dispatch]
+#[derive(Clone)]
+pub enum Texture {
+    ConstantTexture,
+    ImageTexture,
+    CheckerTexture,
+    GradientTexture,
+    NoiseTexture,
+    TurbulenceTexture,
+    MarbleTexture,
+}
+
+impl From<Vec3> for Texture {
+    fn from(color: Vec3) -> Self {
+        ConstantTexture { color }.into()
+    }
+}
+
+#[derive(Clone)]
+pub struct ConstantTexture {
+    pub color: Vec3,
+}
+
+impl TextureTrait for ConstantTexture {
+    fn value(&self, _uv: &Vec2, _point: &Vec3) -> Vec3 {
+        self.color
+    }
+}
+
+/// What happens to texture coordinates outside of [0, 1].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+impl WrapMode {
+    /// Bring a texel coordinate in [0, size[.
+    fn apply(self, texel: i64, size: u32) -> u32 {
+        let size = i64::from(size);
+        let texel = match self {
+            WrapMode::Repeat => texel.rem_euclid(size),
+            WrapMode::MirroredRepeat => {
+                let period = texel.rem_euclid(2 * size);
+                if period < size {
+                    period
+                } else {
+                    2 * size - 1 - period
+                }
+            }
+            WrapMode::ClampToEdge => texel.max(0).min(size - 1),
+        };
+        texel as u32
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TextureError {
+    /// The pixel buffer doesn't hold `width * height` RGBA pixels.
+    InvalidSize { width: u32, height: u32, len: usize },
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextureError::InvalidSize { width, height, len } => {
+                write!(f, "{} bytes is not a {}x{} RGBA8 image", len, width, height)
+            }
+        }
+    }
+}
+
+/// RGBA8 pixels, row by row from the top left corner (the glTF convention).
+pub struct Image {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    /// Linear value of each 8-bit channel value.
+    to_linear: Vec<f32>,
+}
+
+impl Image {
+    /// `srgb` tells if the colours are sRGB encoded, as base colour textures usually are,
+    /// or already linear.
+    pub fn from_rgba8(width: u32, height: u32, data: Vec<u8>, srgb: bool) -> Result<Image, TextureError> {
+        if width == 0 || height == 0 || data.len() != 4 * width as usize * height as usize {
+            return Err(TextureError::InvalidSize {
+                width,
+                height,
+                len: data.len(),
+            });
+        }
+
+        let to_linear = (0..256)
+            .map(|value| {
+                let value = value as f32 / 255.0;
+                if srgb {
+                    srgb_to_linear(value)
+                } else {
+                    value
+                }
+            })
+            .collect();
+
+        Ok(Image {
+            width,
+            height,
+            data,
+            to_linear,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Linear colour of a texel.
+    pub fn texel(&self, x: u32, y: u32) -> Vec3 {
+        let i = 4 * (y as usize * self.width as usize + x as usize);
+        Vec3::new(
+            self.to_linear[self.data[i] as usize],
+            self.to_linear[self.data[i + 1] as usize],
+            self.to_linear[self.data[i + 2] as usize],
+        )
+    }
+}
+
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Inverse of `srgb_to_linear`, for values in [0, 1].
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// An image sampled with bilinear filtering.
+#[derive(Clone)]
+pub struct ImageTexture {
+    pub image: Rc<Image>,
+    pub wrap_u: WrapMode,
+    pub wrap_v: WrapMode,
+}
+
+impl ImageTexture {
+    pub fn new(image: Rc<Image>, wrap: WrapMode) -> ImageTexture {
+        ImageTexture {
+            image,
+            wrap_u: wrap,
+            wrap_v: wrap,
+        }
+    }
+}
+
+impl TextureTrait for ImageTexture {
+    fn value(&self, uv: &Vec2, _point: &Vec3) -> Vec3 {
+        let width = self.image.width();
+        let height = self.image.height();
+
+        // Texel centers are at half coordinates.
+        let x = uv.x * width as f32 - 0.5;
+        let y = uv.y * height as f32 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+
+        let x0 = x0 as i64;
+        let y0 = y0 as i64;
+        let x1 = self.wrap_u.apply(x0 + 1, width);
+        let y1 = self.wrap_v.apply(y0 + 1, height);
+        let x0 = self.wrap_u.apply(x0, width);
+        let y0 = self.wrap_v.apply(y0, height);
+
+        let top = (1.0 - tx) * self.image.texel(x0, y0) + tx * self.image.texel(x1, y0);
+        let bottom = (1.0 - tx) * self.image.texel(x0, y1) + tx * self.image.texel(x1, y1);
+        (1.0 - ty) * top + ty * bottom
+    }
+}
+
+/// Images uploaded from javascript, by id.
+pub struct ImageRegistry {
+    images: HashMap<u32, Rc<Image>>,
+}
+
+impl Default for ImageRegistry {
+    fn default() -> Self {
+        ImageRegistry::new()
+    }
+}
+
+impl ImageRegistry {
+    pub fn new() -> ImageRegistry {
+        ImageRegistry {
+            images: HashMap::new(),
+        }
+    }
+
+    /// Register an image, replacing any image with the same id.
+    /// Textures using the replaced image keep it until their material changes.
+    pub fn add(&mut self, id: u32, image: Image) -> Rc<Image> {
+        let image = Rc::new(image);
+        self.images.insert(id, image.clone());
+        image
+    }
+
+    pub fn find(&self, id: u32) -> Option<Rc<Image>> {
+        self.images.get(&id).cloned()
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        self.images.remove(&id);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::pathtracer::environment::luminance;
+use crate::pathtracer::math::{clamp, saturate};
+use crate::pathtracer::texture::linear_to_srgb;
+use nalgebra_glm::{Mat3, Vec3};
+
+/// How the radiance is brought to the [0, 1] range of the display.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapping {
+    /// Clip the colours above one.
+    Linear,
+    /// `L / (1 + L)` on the luminance, keeping the hue.
+    Reinhard,
+    /// Reinhard reaching white at the luminance `white` instead of at infinity.
+    ExtendedReinhard { white: f32 },
+    /// Stephen Hill's fit of the ACES reference and sRGB output transforms.
+    Aces,
+    /// Troy Sobotka's AgX, with the polynomial approximation of its base contrast.
+    Agx,
+}
+
+impl ToneMapping {
+    /// Linear display colour, in [0, 1], of a radiance scaled by `2^exposure`.
+    pub fn apply(&self, color: &Vec3, exposure: f32) -> Vec3 {
+        let color = color.map(|channel| channel.max(0.)) * exposure.exp2();
+
+        match *self {
+            ToneMapping::Linear => saturate(color),
+            ToneMapping::Reinhard => saturate(scale_luminance(&color, |l| l / (1. + l))),
+            ToneMapping::ExtendedReinhard { white } => {
+                let white2 = (white * white).max(1e-6);
+                saturate(scale_luminance(&color, |l| l * (1. + l / white2) / (1. + l)))
+            }
+            ToneMapping::Aces => saturate(aces(&color)),
+            ToneMapping::Agx => saturate(agx(&color)),
+        }
+    }
+
+    /// Tone mapped colour encoded with the sRGB transfer function, ready for display.
+    pub fn to_srgb(&self, color: &Vec3, exposure: f32) -> Vec3 {
+        self.apply(color, exposure).map(linear_to_srgb)
+    }
+}
+
+/// Scale a colour so that its luminance becomes `curve(luminance)`.
+fn scale_luminance<F: Fn(f32) -> f32>(color: &Vec3, curve: F) -> Vec3 {
+    let l = luminance(color);
+    if l <= 0. {
+        return Vec3::new(0., 0., 0.);
+    }
+    color * (curve(l) / l)
+}
+
+fn aces(color: &Vec3) -> Vec3 {
+    // sRGB to the ACES reference input, with the reference white adjustment.
+    let input = Mat3::new(
+        0.59719, 0.35458, 0.04823,
+        0.07600, 0.90834, 0.01566,
+        0.02840, 0.13383, 0.83777,
+    );
+    // ACES output back to sRGB.
+    let output = Mat3::new(
+        1.60475, -0.53108, -0.07367,
+        -0.10208, 1.10813, -0.00605,
+        -0.00327, -0.07276, 1.07602,
+    );
+
+    let fitted = (input * color).map(|v| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.432951) + 0.238081));
+    output * fitted
+}
+
+fn agx(color: &Vec3) -> Vec3 {
+    // Inset of the primaries, so that saturated colours get to white.
+    let inset = Mat3::new(
+        0.84247905, 0.0784336, 0.079223745,
+        0.042328242, 0.87846863, 0.07916613,
+        0.042375654, 0.0784336, 0.879143,
+    );
+    let outset = Mat3::new(
+        1.196879, -0.09802088, -0.09902974,
+        -0.052896854, 1.1519032, -0.098961174,
+        -0.052971635, -0.09804345, 1.1510737,
+    );
+    // Range of the log encoding, in stops around middle grey.
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    let encoded = (inset * color).map(|v| {
+        let x = clamp((v.max(1e-10).log2() - min_ev) / (max_ev - min_ev), 0., 1.);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    });
+
+    // The curve gives display values, with a gamma of 2.2.
+    (outset * encoded).map(|v| v.max(0.).powf(2.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMapping; 5] = [
+        ToneMapping::Linear,
+        ToneMapping::Reinhard,
+        ToneMapping::ExtendedReinhard { white: 4. },
+        ToneMapping::Aces,
+        ToneMapping::Agx,
+    ];
+
+    fn grey(value: f32) -> Vec3 {
+        Vec3::new(value, value, value)
+    }
+
+    fn assert_close(a: &Vec3, b: &Vec3) {
+        assert!((a - b).amax() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn linear_clips_the_scaled_radiance() {
+        let color = ToneMapping::Linear.apply(&Vec3::new(0.25, 0.5, 2.), 1.);
+        assert_close(&color, &Vec3::new(0.5, 1., 1.));
+
+        let color = ToneMapping::Linear.apply(&Vec3::new(0.5, -1., 4.), -2.);
+        assert_close(&color, &Vec3::new(0.125, 0., 1.));
+    }
+
+    #[test]
+    fn reinhard_compresses_the_luminance_and_keeps_the_hue() {
+        assert_close(&ToneMapping::Reinhard.apply(&grey(1.), 0.), &grey(0.5));
+        assert_close(&ToneMapping::ExtendedReinhard { white: 4. }.apply(&grey(4.), 0.), &grey(1.));
+
+        let color = Vec3::new(0.4, 0.2, 0.1);
+        let mapped = ToneMapping::Reinhard.apply(&color, 0.);
+        assert_close(&(mapped / mapped.x), &(color / color.x));
+        assert!(luminance(&mapped) < luminance(&color));
+    }
+
+    #[test]
+    fn operators_map_radiance_to_the_display_range() {
+        for operator in &OPERATORS {
+            assert_close(&operator.apply(&grey(0.), 0.), &grey(0.));
+            assert_close(&operator.apply(&grey(-1.), 0.), &operator.apply(&grey(0.), 0.));
+
+            // Brighter greys never get darker, and stay in [0, 1].
+            let mut previous = 0.;
+            for step in 0..100 {
+                let value = operator.apply(&grey(0.01 * 1.1f32.powi(step)), 0.).x;
+                assert!(value >= previous && value <= 1., "{:?} at step {}", operator, step);
+                previous = value;
+            }
+            assert!(previous > 0.9, "{:?} only reaches {}", operator, previous);
+        }
+    }
+
+    #[test]
+    fn srgb_output_is_encoded_for_display() {
+        let color = ToneMapping::Linear.to_srgb(&Vec3::new(0., 0.5, 1.), 0.);
+        assert_close(&color, &Vec3::new(0., 0.735357, 1.));
+    }
+}
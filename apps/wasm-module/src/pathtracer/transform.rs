@@ -0,0 +1,148 @@
+use crate::pathtracer::bvh::Aabb;
+use crate::pathtracer::camera::Ray;
+use nalgebra_glm::{
+    determinant, inverse, mat4_to_mat3, quat_angle_axis, quat_to_mat4, scaling, translation, transpose,
+    vec4_to_vec3, Mat3, Mat4, Quat, Vec3, Vec4,
+};
+
+/// Placement of an object in the world.
+///
+/// Objects are intersected in their own space: the ray is brought in object space
+/// with the inverse matrix, and the normals are brought back with the inverse transpose
+/// so that they stay perpendicular to the surface under non-uniform scale.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transform {
+    matrix: Mat4,
+    inverse: Mat4,
+    normal_matrix: Mat3,
+    /// Determinant of the linear part, the factor applied to volumes.
+    determinant: f32,
+    identity: bool,
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform {
+            matrix: Mat4::identity(),
+            inverse: Mat4::identity(),
+            normal_matrix: Mat3::identity(),
+            determinant: 1.0,
+            identity: true,
+        }
+    }
+
+    /// Object to world matrix. Must be invertible.
+    pub fn from_matrix(matrix: Mat4) -> Transform {
+        let inverse = inverse(&matrix);
+        Transform {
+            matrix,
+            inverse,
+            normal_matrix: transpose(&mat4_to_mat3(&inverse)),
+            determinant: determinant(&mat4_to_mat3(&matrix)),
+            identity: matrix == Mat4::identity(),
+        }
+    }
+
+    /// Scale, then rotate, then translate.
+    pub fn new(translation_vector: Vec3, rotation: Quat, scale: Vec3) -> Transform {
+        Transform::from_matrix(
+            translation(&translation_vector) * quat_to_mat4(&rotation.normalize()) * scaling(&scale),
+        )
+    }
+
+    /// Same as `new`, with the rotation given as Euler angles in radians applied
+    /// in the X, Y, Z order of Three.js.
+    pub fn from_euler(translation_vector: Vec3, rotation: Vec3, scale: Vec3) -> Transform {
+        Transform::new(translation_vector, euler_to_quat(&rotation), scale)
+    }
+
+    pub fn from_translation(translation_vector: Vec3) -> Transform {
+        Transform::from_matrix(translation(&translation_vector))
+    }
+
+    /// Same rotation and scale, moved to `translation_vector`.
+    pub fn with_translation(&self, translation_vector: Vec3) -> Transform {
+        let mut matrix = self.matrix;
+        matrix.set_column(3, &translation_vector.push(1.0));
+        Transform::from_matrix(matrix)
+    }
+
+    pub fn matrix(&self) -> &Mat4 {
+        &self.matrix
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.identity
+    }
+
+    /// Bring a world ray in object space. The direction is not normalized, so
+    /// distances along the ray are the same in both spaces.
+    pub fn ray_to_local(&self, ray: &Ray) -> Ray {
+        if self.identity {
+            return Ray {
+                origin: ray.origin,
+                direction: ray.direction,
+            };
+        }
+
+        Ray {
+            origin: vec4_to_vec3(&(self.inverse * ray.origin.push(1.0))),
+            direction: vec4_to_vec3(&(self.inverse * ray.direction.push(0.0))),
+        }
+    }
+
+    pub fn point_to_world(&self, point: &Vec3) -> Vec3 {
+        if self.identity {
+            return *point;
+        }
+
+        vec4_to_vec3(&(self.matrix * point.push(1.0)))
+    }
+
+    /// Bring a normal in world space. The result is normalized.
+    pub fn normal_to_world(&self, normal: &Vec3) -> Vec3 {
+        if self.identity {
+            return normal.normalize();
+        }
+
+        (self.normal_matrix * normal).normalize()
+    }
+
+    /// Factor applied to a small surface area around a point of object space
+    /// with the given unit normal, when it is brought in world space.
+    pub fn area_scale(&self, normal: &Vec3) -> f32 {
+        if self.identity {
+            return 1.0;
+        }
+
+        // Nanson's formula: dA' = det(M) |M^-T n| dA
+        self.determinant.abs() * (self.normal_matrix * normal).magnitude()
+    }
+
+    /// World bounding box of an object space bounding box.
+    pub fn aabb_to_world(&self, aabb: &Aabb) -> Aabb {
+        if self.identity || aabb.is_empty() {
+            return *aabb;
+        }
+
+        let mut bounds = Aabb::empty();
+        for corner in 0..8 {
+            let point = Vec4::new(
+                if corner & 1 == 0 { aabb.min.x } else { aabb.max.x },
+                if corner & 2 == 0 { aabb.min.y } else { aabb.max.y },
+                if corner & 4 == 0 { aabb.min.z } else { aabb.max.z },
+                1.0,
+            );
+            bounds = bounds.grow(&vec4_to_vec3(&(self.matrix * point)));
+        }
+        bounds
+    }
+}
+
+/// Quaternion of the Euler angles (radians) applied in the X, Y, Z order.
+pub fn euler_to_quat(rotation: &Vec3) -> Quat {
+    let x = quat_angle_axis(rotation.x, &Vec3::new(1.0, 0.0, 0.0));
+    let y = quat_angle_axis(rotation.y, &Vec3::new(0.0, 1.0, 0.0));
+    let z = quat_angle_axis(rotation.z, &Vec3::new(0.0, 0.0, 1.0));
+    x * y * z
+}
@@ -1,14 +1,24 @@
 use crate::pathtracer::camera::Ray;
-use crate::pathtracer::material::Material;
-use nalgebra_glm::Vec3;
-use crate::pathtracer::hit::{Hit, Hitable};
+use crate::pathtracer::material::{Material, MaterialTrait};
+use nalgebra_glm::{Vec2, Vec3};
+use crate::pathtracer::hit::{Hit, Hitable, SurfaceSample};
+use crate::pathtracer::bvh::Aabb;
+use crate::pathtracer::transform::Transform;
+use rand::rngs::SmallRng;
+use rand::Rng;
 
 pub struct Triangle {
     id: u32,
     pub vertex_a: Vec3,
     pub vertex_b: Vec3,
     pub vertex_c: Vec3,
+    /// Texture coordinates of the vertices a, b and c.
+    pub uvs: [Vec2; 3],
     pub material: Material,
+    /// Id of the material, for the material id pass.
+    pub material_id: u32,
+    /// Applied on top of the vertices.
+    pub transform: Transform,
 }
 
 impl Triangle {
@@ -18,70 +28,161 @@ impl Triangle {
             vertex_a,
             vertex_b,
             vertex_c,
+            uvs: [Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(0.0, 1.0)],
             material,
+            material_id: 0,
+            transform: Transform::identity(),
         }
     }
+
+    /// Density of a uniform sample on the triangle, per unit of world area.
+    fn surface_pdf(&self) -> f32 {
+        let cross = (self.vertex_b - self.vertex_a).cross(&(self.vertex_c - self.vertex_a));
+        let area = 0.5 * cross.magnitude();
+        1. / (area * self.transform.area_scale(&cross.normalize()))
+    }
 }
 
-impl Hitable for Triangle {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
-        // Source: https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
-        //
-        //        + a
-        //        |\
-        //        | \
-        //  v0v1  |  \ v0v2
-        //        |   \
-        //        |    \
-        //        |     \
-        //      b +------+ c
+/// Intersection of a ray with a triangle.
+/// `u` and `v` are the barycentric coordinates of the hit point relative to `b` and `c`.
+pub struct TriangleHit {
+    pub t: f32,
+    pub u: f32,
+    pub v: f32,
+}
 
-        let v0v1: Vec3 = self.vertex_b - self.vertex_a;
-        let v0v2: Vec3 = self.vertex_c - self.vertex_a;
-        const K_EPSILON: f32 = 0.0000001;
+/// Intersect a ray with the triangle `abc`.
+/// The ray direction doesn't have to be normalized, `t` is expressed in its units.
+pub fn intersect_triangle(
+    vertex_a: &Vec3,
+    vertex_b: &Vec3,
+    vertex_c: &Vec3,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<TriangleHit> {
+    // Source: https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+    //
+    //        + a
+    //        |\
+    //        | \
+    //  v0v1  |  \ v0v2
+    //        |   \
+    //        |    \
+    //        |     \
+    //      b +------+ c
 
-        let plane: Vec3 = ray.direction.cross(&v0v2);
-        let angle_triangle_to_camera: f32 = v0v1.dot(&plane);
+    let v0v1: Vec3 = vertex_b - vertex_a;
+    let v0v2: Vec3 = vertex_c - vertex_a;
+    const K_EPSILON: f32 = 0.0000001;
 
-        // Parallel ?
-        // To enable backface culling, remove the "angle_triangle_to_camera >= 0.0" check.
-        if angle_triangle_to_camera < K_EPSILON && angle_triangle_to_camera >= 0.0 {
-            return None;
-        }
+    let plane: Vec3 = ray.direction.cross(&v0v2);
+    let angle_triangle_to_camera: f32 = v0v1.dot(&plane);
 
-        let f: f32 = 1. / angle_triangle_to_camera;
-        let s: Vec3 = ray.origin - self.vertex_a;
-        let u: f32 = f * s.dot(&plane);
+    // Parallel ?
+    // To enable backface culling, check "angle_triangle_to_camera < K_EPSILON" instead.
+    if (0.0..K_EPSILON).contains(&angle_triangle_to_camera) {
+        return None;
+    }
 
-        if u < 0. || u > 1. {
-            return None;
-        }
+    let f: f32 = 1. / angle_triangle_to_camera;
+    let s: Vec3 = ray.origin - vertex_a;
+    let u: f32 = f * s.dot(&plane);
 
-        let q: Vec3 = s.cross(&v0v1);
-        let v: f32 = f * ray.direction.dot(&q);
+    if !(0. ..=1.).contains(&u) {
+        return None;
+    }
 
-        if v < 0. || u + v > 1. {
-            return None;
-        }
+    let q: Vec3 = s.cross(&v0v1);
+    let v: f32 = f * ray.direction.dot(&q);
 
-        let t: f32 = f * v0v2.dot(&q);
+    if v < 0. || u + v > 1. {
+        return None;
+    }
 
-        // Is the triangle behind us or outside bounds of the test
-        if t <= 0. || t < t_min || t >= t_max {
-            return None;
-        }
+    let t: f32 = f * v0v2.dot(&q);
 
-        let normal: Vec3 = v0v1.cross(&v0v2).normalize();
+    // Is the triangle behind us or outside bounds of the test
+    if t <= 0. || t < t_min || t >= t_max {
+        return None;
+    }
+
+    Some(TriangleHit { t, u, v })
+}
+
+/// Barycentric coordinates `(u, v)` of a point picked uniformly on a triangle.
+pub fn sample_triangle(rng: &mut SmallRng) -> (f32, f32) {
+    let r1: f32 = rng.gen_range(0., 1.);
+    let r2: f32 = rng.gen_range(0., 1.);
+    let sqrt_r1 = r1.sqrt();
+    (sqrt_r1 * (1. - r2), sqrt_r1 * r2)
+}
+
+/// Bounding box of the triangle `abc`.
+pub fn triangle_bounding_box(vertex_a: &Vec3, vertex_b: &Vec3, vertex_c: &Vec3) -> Aabb {
+    Aabb::new(*vertex_a, *vertex_a)
+        .grow(vertex_b)
+        .grow(vertex_c)
+        .pad(0.0001)
+}
+
+impl Hitable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+        let triangle_hit = intersect_triangle(
+            &self.vertex_a,
+            &self.vertex_b,
+            &self.vertex_c,
+            &self.transform.ray_to_local(ray),
+            t_min,
+            t_max,
+        )?;
+
+        let v0v1: Vec3 = self.vertex_b - self.vertex_a;
+        let v0v2: Vec3 = self.vertex_c - self.vertex_a;
+        let normal: Vec3 = self.transform.normal_to_world(&v0v1.cross(&v0v2));
 
         Some(Hit {
-            t,
-            point: ray.point_at_parameter(t),
+            t: triangle_hit.t,
+            point: ray.point_at_parameter(triangle_hit.t),
             normal,
-            material: self.material.clone()
+            geometric_normal: normal,
+            uv: (1.0 - triangle_hit.u - triangle_hit.v) * self.uvs[0]
+                + triangle_hit.u * self.uvs[1]
+                + triangle_hit.v * self.uvs[2],
+            material: self.material.clone(),
+            material_id: self.material_id,
+            object_id: self.id,
+            surface_pdf: if self.material.is_emissive() { self.surface_pdf() } else { 0. },
         })
     }
 
     fn id(&self) -> u32 {
         self.id
     }
+
+    fn bounding_box(&self) -> Aabb {
+        self.transform.aabb_to_world(
+            &triangle_bounding_box(&self.vertex_a, &self.vertex_b, &self.vertex_c))
+    }
+
+    fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn sample_surface(&self, rng: &mut SmallRng) -> SurfaceSample {
+        let (u, v) = sample_triangle(rng);
+        let point = (1. - u - v) * self.vertex_a + u * self.vertex_b + v * self.vertex_c;
+        let local_normal = (self.vertex_b - self.vertex_a).cross(&(self.vertex_c - self.vertex_a)).normalize();
+
+        SurfaceSample {
+            point: self.transform.point_to_world(&point),
+            normal: self.transform.normal_to_world(&local_normal),
+            uv: (1. - u - v) * self.uvs[0] + u * self.uvs[1] + v * self.uvs[2],
+            pdf: self.surface_pdf(),
+        }
+    }
 }
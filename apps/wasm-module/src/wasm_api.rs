@@ -1,23 +1,70 @@
+// The exported functions take flat numbers, which cross the wasm boundary without copies.
+#![allow(clippy::too_many_arguments)]
+
 use wasm_bindgen::prelude::*;
-use nalgebra_glm::Vec3;
-use nalgebra_glm::sqrt;
-use crate::pathtracer::camera::{Camera};
-use crate::pathtracer::PathTracer;
+use nalgebra_glm::{quat, Mat4, Quat, Vec3};
+use crate::pathtracer::camera::{Camera, Projection};
+use crate::pathtracer::session::RenderSession;
+use crate::pathtracer::aov::Aovs;
+use crate::pathtracer::denoise::Denoiser;
+use crate::pathtracer::filter::{Filter, PixelFilter};
+use crate::pathtracer::tonemap::ToneMapping;
+use crate::pathtracer::{Integrator, PathTracer};
 use crate::utils::set_panic_hook;
-use crate::pathtracer::material::LambertianMaterial;
-use crate::pathtracer::hit::HitableShape;
-use crate::pathtracer::math::saturate;
+use crate::pathtracer::material::{EmissiveMaterial, LambertianMaterial, Material};
+use crate::pathtracer::hit::{Hitable, HitableShape};
 use crate::pathtracer::sphere::Sphere;
 use crate::pathtracer::triangle::Triangle;
 use crate::pathtracer::pointlight::PointLight;
+use crate::pathtracer::spotlight::SpotLight;
+use crate::pathtracer::directionallight::DirectionalLight;
+use crate::pathtracer::arealight::{DiskLight, RectLight};
+use crate::pathtracer::environment::{ConstantEnvironment, Environment, GradientEnvironment, ImageEnvironment};
+use crate::pathtracer::hdr::HdrImage;
+use crate::pathtracer::sky::{solar_position, sun_direction, sun_irradiance, SkyEnvironment, SUN_ANGULAR_DIAMETER};
+use crate::pathtracer::mesh::{Mesh, MeshInstance};
+use crate::pathtracer::transform::Transform;
+use crate::pathtracer::texture::{srgb_to_linear, Image, ImageTexture, WrapMode};
+use crate::pathtracer::math::clamp;
+use crate::pathtracer::procedural::{CheckerTexture, GradientTexture, MarbleTexture, NoiseTexture, Perlin, TurbulenceTexture};
+use std::rc::Rc;
 
 #[wasm_bindgen]
 pub struct Context {
     pub camera_pos: Vector3,
     pub camera_rotation: Vector3,
     pub camera_fov: f32,
+    /// Radius of the lens, zero for a pinhole camera.
+    pub camera_aperture: f32,
+    pub camera_focus_distance: f32,
+    /// Number of diaphragm blades. Below three, the bokeh is round.
+    pub camera_blades: u32,
+    pub camera_projection: CameraProjection,
+    /// Height of the view of the orthographic projection, in world units.
+    pub camera_ortho_height: f32,
     pub sample_per_pixel: u16,
-    pathtracer: PathTracer
+    pub sampling: Sampling,
+    /// Maximum number of surfaces a path bounces on.
+    pub max_bounces: u32,
+    pub filter: ReconstructionFilter,
+    /// Radius of the filter in pixels, zero for the usual radius of the filter.
+    pub filter_radius: f32,
+    pub tone_mapping: ToneMappingOperator,
+    /// Exposure compensation in stops, applied before the tone mapping.
+    pub exposure: f32,
+    /// Luminance mapped to white by the extended Reinhard operator.
+    pub white_point: f32,
+    /// Keep the auxiliary outputs of the renders, for the passes and the denoiser.
+    /// They take about 80 bytes per pixel, so they are off unless asked for.
+    /// Turning them on starts the progressive rendering over.
+    pub keep_passes: bool,
+    /// Camera placement replacing `camera_pos` and `camera_rotation`.
+    camera_frame: Option<CameraFrame>,
+    pathtracer: PathTracer,
+    /// Incremented whenever the image to render changes.
+    scene_version: u64,
+    session: RenderSession,
+    denoiser: Denoiser,
 }
 
 #[wasm_bindgen]
@@ -39,8 +86,25 @@ impl Context {
             camera_pos: Vector3::new(0.0, 0.0, 0.0),
             camera_rotation: Vector3::new(0.0, 0.0, 0.0),
             camera_fov: 0.0,
+            camera_aperture: 0.0,
+            camera_focus_distance: 1.0,
+            camera_blades: 0,
+            camera_projection: CameraProjection::Perspective,
+            camera_ortho_height: 10.0,
             sample_per_pixel: 1,
-            pathtracer
+            sampling: Sampling::Mis,
+            max_bounces: 10,
+            filter: ReconstructionFilter::Box,
+            filter_radius: 0.0,
+            tone_mapping: ToneMappingOperator::Linear,
+            exposure: 0.0,
+            white_point: 4.0,
+            keep_passes: false,
+            camera_frame: None,
+            pathtracer,
+            scene_version: 0,
+            session: RenderSession::new(),
+            denoiser: Denoiser::default(),
         }
     }
 
@@ -52,84 +116,357 @@ impl Context {
         width: u32,
         height: u32,
     ) -> Result<Vec<u8>, JsValue> {
-        let camera = Camera::new(
-            self.camera_pos.into(),
-            self.camera_rotation.into(),
-            self.camera_fov,
-            width,
-            height,
-        );
+        let colors = self.render_tile(tile_x, tile_y, tile_size, width, height, false);
+        Ok(to_rgba8(&colors, self.tone_mapping(), self.exposure))
+    }
 
-        self.pathtracer.camera = camera;
-        self.pathtracer.samples = self.sample_per_pixel;
+    /// Like `draw`, but adds `sample_per_pixel` samples to the ones of the previous calls
+    /// and returns their running average. The samples are forgotten when the camera,
+    /// the scene, the rendering settings or the image size change.
+    pub fn draw_progressive(
+        &mut self,
+        tile_x: u32,
+        tile_y: u32,
+        tile_size: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<u8>, JsValue> {
+        let colors = self.render_tile(tile_x, tile_y, tile_size, width, height, true);
+        Ok(to_rgba8(&colors, self.tone_mapping(), self.exposure))
+    }
 
-        // Call the pathtracer once per pixel and build the image
-        let data_size = (tile_size * tile_size) as usize;
-        let mut data = Vec::with_capacity(data_size);
+    /// Like `draw`, but returns the linear radiance as RGBA floats, with an alpha of one.
+    pub fn draw_hdr(
+        &mut self,
+        tile_x: u32,
+        tile_y: u32,
+        tile_size: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<f32>, JsValue> {
+        let colors = self.render_tile(tile_x, tile_y, tile_size, width, height, false);
+        Ok(to_rgba32(&colors))
+    }
 
-        for y in (tile_y..(tile_y + tile_size)).rev() {
-            for x in tile_x..(tile_x + tile_size) {
-                let col = self.pathtracer.compute_pixel(x, y);
-                let better_color = saturate(sqrt(&col));
-                data.push((255.99 * better_color.x) as u8);
-                data.push((255.99 * better_color.y) as u8);
-                data.push((255.99 * better_color.z) as u8);
-                data.push(255);
+    /// Like `draw_progressive`, but returns the linear radiance as RGBA floats, with an alpha of one.
+    pub fn draw_hdr_progressive(
+        &mut self,
+        tile_x: u32,
+        tile_y: u32,
+        tile_size: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<f32>, JsValue> {
+        let colors = self.render_tile(tile_x, tile_y, tile_size, width, height, true);
+        Ok(to_rgba32(&colors))
+    }
+
+    /// Linear radiance of the whole image as RGBA floats, from its top row like the tiles.
+    /// Each pixel holds what the last draw of its tile gave, black if it wasn't drawn
+    /// since the image last changed.
+    pub fn frame_hdr(&self) -> Vec<f32> {
+        let (width, height) = (self.session.width(), self.session.height());
+        to_rgba32(&self.session_colors(0, 0, width, height))
+    }
+
+    /// Tone map again the last render of a tile, for new tone mapping settings.
+    pub fn tone_map_tile(&self, tile_x: u32, tile_y: u32, tile_size: u32) -> Vec<u8> {
+        let colors = self.session_colors(tile_x, tile_y, tile_size, tile_size);
+        to_rgba8(&colors, self.tone_mapping(), self.exposure)
+    }
+
+    /// Tone map again the last render of the whole image, for new tone mapping settings.
+    pub fn tone_map_frame(&self) -> Vec<u8> {
+        let (width, height) = (self.session.width(), self.session.height());
+        to_rgba8(&self.session_colors(0, 0, width, height), self.tone_mapping(), self.exposure)
+    }
+
+    /// Settings of the denoiser. The sigmas are the tolerances to the differences of
+    /// colour, normal and albedo between neighbouring pixels. At most 16 iterations are run.
+    pub fn set_denoiser(&mut self, iterations: u32, color_sigma: f32, normal_sigma: f32, albedo_sigma: f32) {
+        self.denoiser = Denoiser {
+            iterations,
+            color_sigma,
+            normal_sigma,
+            albedo_sigma,
+        };
+    }
+
+    /// Denoised linear radiance of the last render of the whole image, as RGBA floats
+    /// from its top row. Only the tiles drawn by this context are known, see `denoise_image_hdr`.
+    /// The edges are only kept sharp with the albedo and normal passes, see `keep_passes`.
+    pub fn denoise_frame_hdr(&self) -> Vec<f32> {
+        to_rgba32(&self.denoised_frame())
+    }
+
+    /// Denoised, tone mapped and sRGB encoded last render of the whole image.
+    pub fn denoise_frame(&self) -> Vec<u8> {
+        to_rgba8(&self.denoised_frame(), self.tone_mapping(), self.exposure)
+    }
+
+    /// Denoise an image assembled from the tiles of several contexts, such as the workers
+    /// of the viewer: a context only has the tiles it rendered itself.
+    /// `color` holds RGBA floats like `frame_hdr`, `albedo` and `normal` three floats per
+    /// pixel like `pass_frame`, all from the top row. Returns RGBA floats.
+    pub fn denoise_image_hdr(&self, width: u32, height: u32, color: Vec<f32>, albedo: Vec<f32>, normal: Vec<f32>) -> Result<Vec<f32>, JsValue> {
+        Ok(to_rgba32(&self.denoised_image(width, height, &color, &albedo, &normal)?))
+    }
+
+    /// Like `denoise_image_hdr`, but tone mapped and sRGB encoded.
+    pub fn denoise_image(&self, width: u32, height: u32, color: Vec<f32>, albedo: Vec<f32>, normal: Vec<f32>) -> Result<Vec<u8>, JsValue> {
+        let colors = self.denoised_image(width, height, &color, &albedo, &normal)?;
+        Ok(to_rgba8(&colors, self.tone_mapping(), self.exposure))
+    }
+
+    /// Forget the samples accumulated by `draw_progressive`.
+    pub fn reset_accumulation(&mut self) {
+        self.session.reset();
+    }
+
+    /// Number of samples of a pixel, accumulated by `draw_progressive`,
+    /// counting the rows from the bottom of the image.
+    pub fn accumulated_samples(&self, x: u32, y: u32) -> u32 {
+        self.session.samples(x, y)
+    }
+
+    /// Auxiliary output of the last render of a tile, from its top row. The depth has
+    /// one float per pixel, the other passes have three.
+    /// The passes are only kept when `keep_passes` is set, they are empty otherwise.
+    pub fn pass_tile(&self, pass: RenderPass, tile_x: u32, tile_y: u32, tile_size: u32) -> Vec<f32> {
+        to_pass(&self.session_aovs(tile_x, tile_y, tile_size, tile_size), pass)
+    }
+
+    /// Auxiliary output of the last render of the whole image, from its top row.
+    pub fn pass_frame(&self, pass: RenderPass) -> Vec<f32> {
+        let (width, height) = (self.session.width(), self.session.height());
+        to_pass(&self.session_aovs(0, 0, width, height), pass)
+    }
+
+    /// Ids of the last render of a tile, from its top row.
+    /// Pixels where nothing was hit have the id 0xFFFFFFFF.
+    pub fn id_pass_tile(&self, pass: IdPass, tile_x: u32, tile_y: u32, tile_size: u32) -> Vec<u32> {
+        to_id_pass(&self.session_aovs(tile_x, tile_y, tile_size, tile_size), pass)
+    }
+
+    /// Ids of the last render of the whole image, from its top row.
+    pub fn id_pass_frame(&self, pass: IdPass) -> Vec<u32> {
+        let (width, height) = (self.session.width(), self.session.height());
+        to_id_pass(&self.session_aovs(0, 0, width, height), pass)
+    }
+
+    /// Place the camera at `eye`, looking at `target`, with `up` pointing up in the image.
+    pub fn set_camera_look_at(
+        &mut self,
+        eye_x: f32, eye_y: f32, eye_z: f32,
+        target_x: f32, target_y: f32, target_z: f32,
+        up_x: f32, up_y: f32, up_z: f32,
+    ) {
+        self.camera_frame = Some(CameraFrame::LookAt {
+            eye: Vec3::new(eye_x, eye_y, eye_z),
+            target: Vec3::new(target_x, target_y, target_z),
+            up: Vec3::new(up_x, up_y, up_z),
+        });
+    }
+
+    /// Place the camera with the position and quaternion of a Three.js camera.
+    pub fn set_camera_quaternion(&mut self, x: f32, y: f32, z: f32, q_x: f32, q_y: f32, q_z: f32, q_w: f32) {
+        self.camera_frame = Some(CameraFrame::Quaternion {
+            position: Vec3::new(x, y, z),
+            rotation: quat(q_x, q_y, q_z, q_w),
+        });
+    }
+
+    /// Use the view and projection matrices of a camera, 16 values each in column-major
+    /// order like the `elements` of a Three.js matrix. The projection matrix replaces
+    /// `camera_fov`, and gives an orthographic camera when it is orthographic.
+    pub fn set_camera_matrices(&mut self, view: Vec<f32>, projection: Vec<f32>) -> bool {
+        match (to_mat4(&view), to_mat4(&projection)) {
+            (Some(view), Some(projection)) => {
+                self.camera_frame = Some(CameraFrame::Matrices { view, projection });
+                true
             }
+            _ => false,
         }
+    }
 
-        Ok(data)
+    /// Go back to placing the camera with `camera_pos` and `camera_rotation`.
+    pub fn reset_camera_frame(&mut self) {
+        self.camera_frame = None;
     }
 
     /// Create a new light or edit an existing one.
-    pub fn create_or_edit_light(&mut self, id: u32, x: f32, y: f32, z: f32, intensity: f32)
+    /// The colour is sRGB encoded, like the colour pickers give it, and multiplied by `intensity`.
+    pub fn create_or_edit_light(&mut self, id: u32, x: f32, y: f32, z: f32, r: u32, g: u32, b: u32, intensity: f32)
     {
-        // Check if the light already exists.
-        match self.pathtracer.lights.find(id) {
-            // Edit the light.
-            Some(light) => {
-                light.position.x = x;
-                light.position.y = y;
-                light.position.z = z;
-                light.intensity = intensity;
-            },
-            // Create a new light.
-            None => {
-                let light = PointLight::new(
-                    id,
-                    Vec3::new(x, y, z),
-                    intensity);
-                self.pathtracer.lights.add(light.into());
-            }
-        }
+        self.create_or_edit_point_light(id, Vec3::new(x, y, z), intensity * to_color(r, g, b));
+    }
+
+    /// Create a new light or edit an existing one, with the colour of a black body
+    /// at the given temperature in Kelvin.
+    pub fn create_or_edit_light_temperature(&mut self, id: u32, x: f32, y: f32, z: f32, kelvin: f32, intensity: f32)
+    {
+        self.scene().lights.add_or_replace(PointLight::from_temperature(id, Vec3::new(x, y, z), kelvin, intensity).into());
+    }
+
+    /// Create a new spot light or edit an existing one.
+    /// The angles are the half angles of the cones, in degrees.
+    pub fn create_or_edit_spot_light(
+        &mut self,
+        id: u32,
+        x: f32, y: f32, z: f32,
+        dir_x: f32, dir_y: f32, dir_z: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+        r: u32, g: u32, b: u32,
+        intensity: f32,
+    ) {
+        self.scene().lights.add_or_replace(SpotLight::new(
+            id,
+            Vec3::new(x, y, z),
+            Vec3::new(dir_x, dir_y, dir_z),
+            intensity * to_color(r, g, b),
+            inner_angle.to_radians(),
+            outer_angle.to_radians(),
+        ).into());
+    }
+
+    /// Create a new directional light or edit an existing one.
+    /// The direction is the one the light travels along, the angular diameter
+    /// of the source is in degrees. The sun is about half a degree wide.
+    pub fn create_or_edit_directional_light(
+        &mut self,
+        id: u32,
+        dir_x: f32, dir_y: f32, dir_z: f32,
+        angular_diameter: f32,
+        r: u32, g: u32, b: u32,
+        intensity: f32,
+    ) {
+        self.scene().lights.add_or_replace(DirectionalLight::new(
+            id,
+            Vec3::new(dir_x, dir_y, dir_z),
+            intensity * to_color(r, g, b),
+            angular_diameter.to_radians(),
+        ).into());
     }
 
+    /// Create a new rectangle light or edit an existing one.
+    /// The rectangle is centered on the position, with the edges `u` and `v`.
+    /// It emits light on the side of `u × v`.
+    pub fn create_or_edit_rect_light(
+        &mut self,
+        id: u32,
+        x: f32, y: f32, z: f32,
+        u_x: f32, u_y: f32, u_z: f32,
+        v_x: f32, v_y: f32, v_z: f32,
+        r: u32, g: u32, b: u32,
+        intensity: f32,
+    ) {
+        self.scene().lights.add_or_replace(RectLight::new(
+            id,
+            Vec3::new(x, y, z),
+            Vec3::new(u_x, u_y, u_z),
+            Vec3::new(v_x, v_y, v_z),
+            intensity * to_color(r, g, b),
+        ).into());
+    }
+
+    /// Create a new disk light or edit an existing one.
+    /// It emits light on the side of its normal.
+    pub fn create_or_edit_disk_light(
+        &mut self,
+        id: u32,
+        x: f32, y: f32, z: f32,
+        normal_x: f32, normal_y: f32, normal_z: f32,
+        radius: f32,
+        r: u32, g: u32, b: u32,
+        intensity: f32,
+    ) {
+        self.scene().lights.add_or_replace(DiskLight::new(
+            id,
+            Vec3::new(x, y, z),
+            Vec3::new(normal_x, normal_y, normal_z),
+            radius,
+            intensity * to_color(r, g, b),
+        ).into());
+    }
+
+    /// Remove a light of any type.
     pub fn remove_light(&mut self, id: u32) {
-        self.pathtracer.lights.remove(id);
+        self.scene().lights.remove(id);
+    }
+
+    /// Light the scene with the same colour from every direction.
+    pub fn set_environment_color(&mut self, r: u32, g: u32, b: u32, intensity: f32) {
+        self.scene().environment = ConstantEnvironment {
+            color: intensity * to_color(r, g, b),
+        }.into();
+    }
+
+    /// Light the scene with a vertical gradient, from the bottom colour straight down
+    /// to the top colour straight up.
+    pub fn set_environment_gradient(&mut self, bottom_r: u32, bottom_g: u32, bottom_b: u32, top_r: u32, top_g: u32, top_b: u32) {
+        self.scene().environment = GradientEnvironment {
+            bottom: to_color(bottom_r, bottom_g, bottom_b),
+            top: to_color(top_r, top_g, top_b),
+        }.into();
+    }
+
+    /// Light the scene with an equirectangular image in the Radiance `.hdr` format.
+    /// The rotation around the vertical axis is in degrees.
+    pub fn set_environment_hdr(&mut self, data: Vec<u8>, strength: f32, rotation: f32) -> Result<(), JsValue> {
+        let image = HdrImage::from_radiance(&data)
+            .map_err(|error| JsValue::from_str(&error.to_string()))?;
+        self.scene().environment = ImageEnvironment::new(Rc::new(image), strength, rotation.to_radians()).into();
+        Ok(())
+    }
+
+    /// Light the scene with a daylight sky and a sun light with the id `sun_id`.
+    /// The sun position is given in degrees, its azimuth goes from the north (-Z)
+    /// to the east (+X). The turbidity goes from 2 for a clear sky to 10 for a hazy one.
+    /// Once the sun has set, there is no sun light and the sky fades to black 6 degrees below the horizon.
+    pub fn set_sky(&mut self, sun_id: u32, elevation: f32, azimuth: f32, turbidity: f32, intensity: f32) {
+        let direction = sun_direction(elevation.to_radians(), azimuth.to_radians());
+        self.set_sky_and_sun(sun_id, direction, turbidity, intensity);
+    }
+
+    /// Same as `set_sky`, with the sun position of a day of the year at a local solar
+    /// time in hours and a latitude in degrees.
+    pub fn set_sky_at(&mut self, sun_id: u32, month: u32, day: u32, hour: f32, latitude: f32, turbidity: f32, intensity: f32) {
+        let (elevation, azimuth) = solar_position(month, day, hour, latitude);
+        self.set_sky_and_sun(sun_id, sun_direction(elevation, azimuth), turbidity, intensity);
+    }
+
+    /// Turn the environment image around the vertical axis, in degrees.
+    /// Returns false when the environment is not an image.
+    pub fn set_environment_rotation(&mut self, rotation: f32) -> bool {
+        match &mut self.scene().environment {
+            Environment::ImageEnvironment(environment) => {
+                environment.rotation = rotation.to_radians();
+                true
+            }
+            _ => false,
+        }
     }
 
     pub fn add_sphere(&mut self, id: u32, x: f32, y: f32, z: f32, radius: f32) {
-        self.pathtracer.world.add(Sphere::new(
+        self.scene().world.add(Sphere::new(
             id,
             Vec3::new(x, y, z),
             radius,
             LambertianMaterial {
-                albedo: Vec3::new(0.5, 0.5, 0.5),
+                albedo: Vec3::new(0.5, 0.5, 0.5).into(),
             }.into(),
         ).into());
     }
 
     pub fn update_sphere(&mut self, id: u32, x: f32, y: f32, z: f32, radius: f32) -> bool {
-        if let Some(shape) = self.pathtracer.world.find(id) {
+        if let Some(shape) = self.scene().world.find_mut(id) {
 
-            match shape {
-                HitableShape::Sphere(sphere) => {
-                    sphere.center.x = x;
-                    sphere.center.y = y;
-                    sphere.center.z = z;
-                    sphere.radius = radius;
-                },
-                _ => ()
+            if let HitableShape::Sphere(sphere) = shape {
+                sphere.center.x = x;
+                sphere.center.y = y;
+                sphere.center.z = z;
+                sphere.radius = radius;
             }
 
             true
@@ -140,7 +477,7 @@ impl Context {
     }
 
     pub fn remove_sphere(&mut self, id: u32) {
-        self.pathtracer.world.remove(id);
+        self.scene().world.remove(id);
     }
 
 
@@ -155,13 +492,13 @@ impl Context {
                         c_x: f32,
                         c_y: f32,
                         c_z: f32) {
-        self.pathtracer.world.add(Triangle::new(
+        self.scene().world.add(Triangle::new(
             id,
             Vec3::new(a_x, a_y, a_z),
             Vec3::new(b_x, b_y, b_z),
             Vec3::new(c_x, c_y, c_z),
             LambertianMaterial {
-                albedo: Vec3::new(0.5, 0.5, 0.5),
+                albedo: Vec3::new(0.5, 0.5, 0.5).into(),
             }.into(),
         ).into());
     }
@@ -178,21 +515,18 @@ impl Context {
                            c_y: f32,
                            c_z: f32
     ) -> bool {
-        if let Some(shape) = self.pathtracer.world.find(id) {
+        if let Some(shape) = self.scene().world.find_mut(id) {
 
-            match shape {
-                HitableShape::Triangle(triangle) => {
-                    triangle.vertex_a.x = a_x;
-                    triangle.vertex_a.y = a_y;
-                    triangle.vertex_a.z = a_z;
-                    triangle.vertex_b.x = b_x;
-                    triangle.vertex_b.y = b_y;
-                    triangle.vertex_b.z = b_z;
-                    triangle.vertex_c.x = c_x;
-                    triangle.vertex_c.y = c_y;
-                    triangle.vertex_c.z = c_z;
-                },
-                _ => ()
+            if let HitableShape::Triangle(triangle) = shape {
+                triangle.vertex_a.x = a_x;
+                triangle.vertex_a.y = a_y;
+                triangle.vertex_a.z = a_z;
+                triangle.vertex_b.x = b_x;
+                triangle.vertex_b.y = b_y;
+                triangle.vertex_b.z = b_z;
+                triangle.vertex_c.x = c_x;
+                triangle.vertex_c.y = c_y;
+                triangle.vertex_c.z = c_z;
             }
 
             true
@@ -203,68 +537,459 @@ impl Context {
     }
 
     pub fn remove_triangle(&mut self, id: u32) {
-        self.pathtracer.world.remove(id);
+        self.scene().world.remove(id);
     }
 
+    /// Add a model from its position buffer (3 floats per vertex), its normal buffer
+    /// (3 floats per vertex, empty to compute them), its texture coordinate buffer
+    /// (2 floats per vertex, may be empty) and its index buffer
+    /// (3 indices per triangle, empty if the model is not indexed).
+    /// Both `Uint16Array` and `Uint32Array` index buffers are accepted.
     pub fn add_model(&mut self, id: u32, x: f32, y: f32, z: f32,
                      vertices: Vec<f32>,
-                     triangles: Vec<u16>) {
-        self.remove_model(id);
-        let pos = Vec3::new(x, y, z);
-        for vertex in vertices.chunks(9) {
-            assert_eq!(vertex.len(), 9);
-            self.pathtracer.world.add(Triangle::new(
-                id,
-                Vec3::new(vertex[0], vertex[1],vertex[2]),
-                Vec3::new(vertex[3], vertex[4],vertex[5]),
-                Vec3::new(vertex[6], vertex[7],vertex[8]),
-                LambertianMaterial {
-                    albedo: Vec3::new(0.5, 0.5, 0.5),
-                }.into(),
-            ).into());
-        }
-        log(self.pathtracer.world.stats().as_str());
-//        for triangle in triangles.chunks(3) {
-//            assert_eq!(triangle.len(), 3);
-//            self.pathtracer.world.add(Triangle::new(
-//                id,
-//                extract_triangle(&vertices, triangle[0]),
-//                extract_triangle(&vertices, triangle[1]),
-//                extract_triangle(&vertices, triangle[2]),
-//                LambertianMaterial {
-//                    albedo: Vec3::new(0.5, 0.5, 0.5),
-//                }.into(),
-//            ).into());
-//        }
+                     normals: Vec<f32>,
+                     uvs: Vec<f32>,
+                     triangles: Vec<u32>) -> Result<(), JsValue> {
+        // A model is a single instance of a mesh that is not shared through the registry.
+        // Invalid buffers leave the existing model alone.
+        let mesh = Rc::new(build_mesh(&vertices, &normals, &uvs, &triangles)?);
+        self.place_model(id, mesh, Transform::from_translation(Vec3::new(x, y, z)));
+        Ok(())
     }
 
+    /// Same as `add_model`, keeping the rotation and scale given to the model by `set_transform`.
+    /// Moving a model is cheaper with `set_transform` alone, which doesn't upload the buffers.
     pub fn update_model(&mut self, id: u32, x: f32, y: f32, z: f32,
                         vertices: Vec<f32>,
-                        triangles: Vec<u16>) -> bool {
-        self.remove_model(id);
-        self.add_model(id, x, y, z, vertices, triangles);
-        log(self.pathtracer.world.stats().as_str());
-        true
+                        normals: Vec<f32>,
+                        uvs: Vec<f32>,
+                        triangles: Vec<u32>) -> Result<bool, JsValue> {
+        let mesh = Rc::new(build_mesh(&vertices, &normals, &uvs, &triangles)?);
+        let pos = Vec3::new(x, y, z);
+        let transform = match self.pathtracer.world.find(id) {
+            Some(HitableShape::MeshInstance(instance)) => instance.transform().with_translation(pos),
+            _ => Transform::from_translation(pos),
+        };
+
+        self.place_model(id, mesh, transform);
+        Ok(true)
     }
 
     pub fn remove_model(&mut self, id: u32) {
-        self.pathtracer.world.remove(id);
+        self.scene().world.remove(id);
+    }
+
+    /// Upload a mesh that can then be instanced with `add_instance`.
+    /// The buffers are the same as in `add_model`. Mesh ids are independent from object ids.
+    pub fn add_mesh(&mut self, mesh_id: u32,
+                    vertices: Vec<f32>,
+                    normals: Vec<f32>,
+                    uvs: Vec<f32>,
+                    triangles: Vec<u32>) -> Result<(), JsValue> {
+        self.pathtracer.meshes.add(mesh_id, build_mesh(&vertices, &normals, &uvs, &triangles)?);
+        Ok(())
+    }
+
+    /// Forget a mesh. Its existing instances still render until they are removed.
+    pub fn remove_mesh(&mut self, mesh_id: u32) {
+        self.pathtracer.meshes.remove(mesh_id);
+    }
+
+    /// Place a mesh in the world. `transform` is a column-major 4x4 matrix, as in Three.js.
+    pub fn add_instance(&mut self, id: u32, mesh_id: u32, transform: Vec<f32>) -> bool {
+        match (self.pathtracer.meshes.find(mesh_id), to_mat4(&transform)) {
+            (Some(mesh), Some(transform)) => {
+                self.scene().world.add(MeshInstance::new(id, mesh, Transform::from_matrix(transform)).into());
+                true
+            },
+            _ => false
+        }
+    }
+
+    pub fn update_instance(&mut self, id: u32, transform: Vec<f32>) -> bool {
+        let transform = match to_mat4(&transform) {
+            Some(transform) => transform,
+            None => return false,
+        };
+
+        match self.scene().world.find_mut(id) {
+            Some(HitableShape::MeshInstance(instance)) => {
+                instance.set_transform(Transform::from_matrix(transform));
+                true
+            },
+            _ => false
+        }
+    }
+
+    /// Place a sphere, a triangle or a model with a translation, a rotation quaternion and a scale.
+    /// For spheres and triangles, the transform is applied on top of their center or vertices.
+    pub fn set_transform(&mut self,
+                         id: u32,
+                         t_x: f32,
+                         t_y: f32,
+                         t_z: f32,
+                         q_x: f32,
+                         q_y: f32,
+                         q_z: f32,
+                         q_w: f32,
+                         s_x: f32,
+                         s_y: f32,
+                         s_z: f32) -> bool {
+        match self.scene().world.find_mut(id) {
+            Some(shape) => {
+                shape.set_transform(Transform::new(
+                    Vec3::new(t_x, t_y, t_z),
+                    quat(q_x, q_y, q_z, q_w),
+                    Vec3::new(s_x, s_y, s_z)));
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Same as `set_transform` with the rotation given as Euler angles in radians, in XYZ order.
+    pub fn set_transform_euler(&mut self,
+                               id: u32,
+                               t_x: f32,
+                               t_y: f32,
+                               t_z: f32,
+                               r_x: f32,
+                               r_y: f32,
+                               r_z: f32,
+                               s_x: f32,
+                               s_y: f32,
+                               s_z: f32) -> bool {
+        match self.scene().world.find_mut(id) {
+            Some(shape) => {
+                shape.set_transform(Transform::from_euler(
+                    Vec3::new(t_x, t_y, t_z),
+                    Vec3::new(r_x, r_y, r_z),
+                    Vec3::new(s_x, s_y, s_z)));
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn remove_instance(&mut self, id: u32) {
+        self.scene().world.remove(id);
+    }
+
+    /// Give an id to the material of a shape, output by the material id pass.
+    /// Shapes sharing a material should get the same id.
+    pub fn set_material_id(&mut self, id: u32, material_id: u32) -> bool {
+        match self.scene().world.find_mut(id) {
+            Some(HitableShape::Sphere(sphere)) => sphere.material_id = material_id,
+            Some(HitableShape::Triangle(triangle)) => triangle.material_id = material_id,
+            Some(HitableShape::MeshInstance(instance)) => instance.material_id = material_id,
+            None => return false,
+        }
+        true
     }
 
     pub fn set_lambert(&mut self, id: u32, r: u32, g: u32, b: u32) -> bool {
-        if let Some(shape) = self.pathtracer.world.find(id) {
+        self.set_material(id, LambertianMaterial {
+            albedo: to_color(r, g, b).into(),
+        }.into())
+    }
+
+    /// Make a shape emit light. The colour is scaled by `strength`, which can exceed one.
+    pub fn set_emissive(&mut self, id: u32, r: u32, g: u32, b: u32, strength: f32) -> bool {
+        self.set_material(id, EmissiveMaterial {
+            emission: to_color(r, g, b).into(),
+            strength,
+        }.into())
+    }
+
+    /// Add a horizontal square at y = 0, with texture coordinates matching the world coordinates.
+    pub fn add_ground_plane(&mut self, id: u32, size: f32) {
+        self.scene().ground_plane(id, size, LambertianMaterial {
+            albedo: Vec3::new(0.5, 0.5, 0.5).into(),
+        }.into());
+    }
+
+    /// Test scene: five spheres in a row on a checkered ground plane.
+    /// The ground plane gets the id `first_id`, the spheres the next five ids.
+    pub fn add_checker_spheres(&mut self, first_id: u32) {
+        self.scene().checker_spheres(first_id);
+    }
+
+    /// Lambertian material alternating between two colours, `scale` squares per texture unit.
+    pub fn set_checker(&mut self, id: u32,
+                       even_r: u32, even_g: u32, even_b: u32,
+                       odd_r: u32, odd_g: u32, odd_b: u32,
+                       scale: f32) -> bool {
+        self.set_material(id, LambertianMaterial {
+            albedo: CheckerTexture::new(
+                to_color(even_r, even_g, even_b).into(),
+                to_color(odd_r, odd_g, odd_b).into(),
+                scale,
+            ).into(),
+        }.into())
+    }
+
+    /// Lambertian material blending between two colours from one world position to another.
+    pub fn set_gradient(&mut self, id: u32,
+                        start_r: u32, start_g: u32, start_b: u32,
+                        end_r: u32, end_g: u32, end_b: u32,
+                        from_x: f32, from_y: f32, from_z: f32,
+                        to_x: f32, to_y: f32, to_z: f32) -> bool {
+        self.set_material(id, LambertianMaterial {
+            albedo: GradientTexture {
+                start: to_color(start_r, start_g, start_b),
+                end: to_color(end_r, end_g, end_b),
+                from: Vec3::new(from_x, from_y, from_z),
+                to: Vec3::new(to_x, to_y, to_z),
+            }.into(),
+        }.into())
+    }
+
+    /// Lambertian material with Perlin noise of the given frequency.
+    pub fn set_noise(&mut self, id: u32, r: u32, g: u32, b: u32, scale: f32) -> bool {
+        self.set_material(id, LambertianMaterial {
+            albedo: NoiseTexture {
+                perlin: seeded_perlin(),
+                color: to_color(r, g, b),
+                scale,
+            }.into(),
+        }.into())
+    }
+
+    /// Lambertian material with turbulent noise summed over `octaves` octaves.
+    pub fn set_turbulence(&mut self, id: u32, r: u32, g: u32, b: u32, scale: f32, octaves: u32) -> bool {
+        self.set_material(id, LambertianMaterial {
+            albedo: TurbulenceTexture {
+                perlin: seeded_perlin(),
+                color: to_color(r, g, b),
+                scale,
+                octaves,
+            }.into(),
+        }.into())
+    }
+
+    /// Lambertian material with marble veins.
+    pub fn set_marble(&mut self, id: u32, r: u32, g: u32, b: u32, scale: f32, turbulence: f32) -> bool {
+        self.set_material(id, LambertianMaterial {
+            albedo: MarbleTexture {
+                perlin: seeded_perlin(),
+                color: to_color(r, g, b),
+                scale,
+                turbulence,
+            }.into(),
+        }.into())
+    }
+
+    /// Upload an RGBA8 image, row by row from the top left corner.
+    /// `srgb` should be true for colour textures such as glTF base colour textures.
+    /// Texture ids are independent from object ids.
+    pub fn add_texture(&mut self, texture_id: u32, width: u32, height: u32, data: Vec<u8>, srgb: bool) -> Result<(), JsValue> {
+        let image = Image::from_rgba8(width, height, data, srgb)
+            .map_err(|error| JsValue::from_str(&error.to_string()))?;
+        self.pathtracer.images.add(texture_id, image);
+        Ok(())
+    }
+
+    /// Forget a texture. The materials already using it keep it.
+    pub fn remove_texture(&mut self, texture_id: u32) {
+        self.pathtracer.images.remove(texture_id);
+    }
+
+    /// Use a texture as the albedo of a lambertian material.
+    pub fn set_lambert_texture(&mut self, id: u32, texture_id: u32, wrapping: Wrapping) -> bool {
+        match self.pathtracer.images.find(texture_id) {
+            Some(image) => self.set_material(id, LambertianMaterial {
+                albedo: ImageTexture::new(image, wrapping.into()).into(),
+            }.into()),
+            None => false
+        }
+    }
+}
+
+impl Context {
+    /// Give the camera and the rendering settings to the pathtracer.
+    fn prepare(&mut self, width: u32, height: u32) {
+        let mut camera = match &self.camera_frame {
+            None => Camera::new(
+                self.camera_pos.into(),
+                self.camera_rotation.into(),
+                self.camera_fov,
+                width,
+                height,
+            ),
+            Some(CameraFrame::LookAt { eye, target, up }) =>
+                Camera::look_at(*eye, *target, *up, self.camera_fov, width, height),
+            Some(CameraFrame::Quaternion { position, rotation }) =>
+                Camera::from_quaternion(*position, *rotation, self.camera_fov, width, height),
+            Some(CameraFrame::Matrices { view, projection }) =>
+                Camera::from_matrices(*view, *projection, width, height),
+        };
+
+        camera.aperture = self.camera_aperture;
+        camera.focus_distance = self.camera_focus_distance;
+        camera.blades = self.camera_blades;
+        match self.camera_projection {
+            CameraProjection::Perspective => {}
+            CameraProjection::Orthographic => camera.projection = Projection::Orthographic {
+                height: self.camera_ortho_height,
+            },
+            CameraProjection::Fisheye => camera.projection = Projection::Fisheye,
+            CameraProjection::Equirectangular => camera.projection = Projection::Equirectangular,
+        }
+
+        // The accumulated samples only hold for the same camera and settings.
+        let integrator = self.sampling.into();
+        let filter: Filter = self.filter.into();
+        let radius = if self.filter_radius > 0. { self.filter_radius } else { filter.default_radius() };
+        let filter = PixelFilter::new(filter, radius);
+        if camera != self.pathtracer.camera
+            || integrator != self.pathtracer.integrator
+            || self.max_bounces != self.pathtracer.max_bounces
+            || filter != self.pathtracer.filter
+        {
+            self.scene_version += 1;
+        }
+
+        self.pathtracer.camera = camera;
+        self.pathtracer.samples = self.sample_per_pixel;
+        self.pathtracer.integrator = integrator;
+        self.pathtracer.max_bounces = self.max_bounces;
+        self.pathtracer.filter = filter;
+    }
+
+    /// Render a tile, from its top row, and keep the result in the session.
+    /// When progressive, the new samples are averaged with the previous ones.
+    fn render_tile(
+        &mut self,
+        tile_x: u32,
+        tile_y: u32,
+        tile_size: u32,
+        width: u32,
+        height: u32,
+        progressive: bool,
+    ) -> Vec<Vec3> {
+        self.prepare(width, height);
+        self.session.keep_aovs(self.keep_passes);
+        self.session.sync(width, height, self.scene_version);
+
+        let tile = self.pathtracer.render_tile(tile_x, tile_y, tile_size, tile_size);
+
+        // Build the image from its top row
+        let samples = self.sample_per_pixel as f32;
+        let mut colors = Vec::with_capacity((tile_size * tile_size) as usize);
+        for y in (tile_y..(tile_y + tile_size)).rev() {
+            for x in tile_x..(tile_x + tile_size) {
+                let index = ((y - tile_y) * tile_size + (x - tile_x)) as usize;
+                let (sum, weight) = (tile.sums[index], tile.weights[index]);
+                if progressive {
+                    self.session.add(x, y, sum, weight);
+                    self.session.add_aovs(x, y, &tile.aovs[index], samples);
+                    colors.push(self.session.average(x, y));
+                } else {
+                    self.session.set(x, y, sum, weight);
+                    self.session.set_aovs(x, y, &tile.aovs[index], samples);
+                    colors.push(if weight > 0. { sum / weight } else { Vec3::new(0., 0., 0.) });
+                }
+            }
+        }
+
+        colors
+    }
+
+    /// Colours kept in the session for a rectangle of the image, from its top row.
+    fn session_colors(&self, x: u32, y: u32, width: u32, height: u32) -> Vec<Vec3> {
+        let mut colors = Vec::with_capacity((width * height) as usize);
+        for y in (y..(y + height)).rev() {
+            for x in x..(x + width) {
+                colors.push(self.session.average(x, y));
+            }
+        }
+        colors
+    }
+
+    /// Auxiliary outputs kept in the session for a rectangle of the image, from its top row.
+    fn session_aovs(&self, x: u32, y: u32, width: u32, height: u32) -> Vec<Aovs> {
+        let mut aovs = Vec::with_capacity((width * height) as usize);
+        for y in (y..(y + height)).rev() {
+            for x in x..(x + width) {
+                aovs.push(self.session.aovs(x, y));
+            }
+        }
+        aovs
+    }
+
+    fn denoised_image(&self, width: u32, height: u32, color: &[f32], albedo: &[f32], normal: &[f32]) -> Result<Vec<Vec3>, JsValue> {
+        let size = (width * height) as usize;
+        if color.len() != size * 4 || albedo.len() != size * 3 || normal.len() != size * 3 {
+            return Err(JsValue::from_str(&format!(
+                "expected {} colour, {} albedo and {} normal floats for a {}x{} image, got {}, {} and {}",
+                size * 4, size * 3, size * 3, width, height, color.len(), albedo.len(), normal.len())));
+        }
+
+        let to_vec3 = |data: &[f32], stride: usize| -> Vec<Vec3> {
+            data.chunks_exact(stride).map(|pixel| Vec3::new(pixel[0], pixel[1], pixel[2])).collect()
+        };
+        Ok(self.denoiser.denoise(width, height, &to_vec3(color, 4), &to_vec3(albedo, 3), &to_vec3(normal, 3)))
+    }
+
+    fn denoised_frame(&self) -> Vec<Vec3> {
+        let (width, height) = (self.session.width(), self.session.height());
+        let colors = self.session_colors(0, 0, width, height);
+        let aovs = self.session_aovs(0, 0, width, height);
+        let albedo: Vec<Vec3> = aovs.iter().map(|aov| aov.albedo).collect();
+        let normal: Vec<Vec3> = aovs.iter().map(|aov| aov.normal).collect();
+        self.denoiser.denoise(width, height, &colors, &albedo, &normal)
+    }
+
+    fn tone_mapping(&self) -> ToneMapping {
+        match self.tone_mapping {
+            ToneMappingOperator::Linear => ToneMapping::Linear,
+            ToneMappingOperator::Reinhard => ToneMapping::Reinhard,
+            ToneMappingOperator::ExtendedReinhard => ToneMapping::ExtendedReinhard { white: self.white_point },
+            ToneMappingOperator::Aces => ToneMapping::Aces,
+            ToneMappingOperator::Agx => ToneMapping::Agx,
+        }
+    }
+
+    /// Access to the scene for editing it, which invalidates the accumulated samples.
+    fn place_model(&mut self, id: u32, mesh: Rc<Mesh>, transform: Transform) {
+        self.remove_model(id);
+        self.scene().world.add(MeshInstance::new(id, mesh, transform).into());
+        log(self.pathtracer.world.stats().as_str());
+    }
+
+    fn scene(&mut self) -> &mut PathTracer {
+        self.scene_version += 1;
+        &mut self.pathtracer
+    }
+
+    fn set_sky_and_sun(&mut self, sun_id: u32, direction: Vec3, turbidity: f32, intensity: f32) {
+        self.scene().environment = SkyEnvironment::new(direction, turbidity, intensity).into();
+
+        // The sun only lights the scene during the day.
+        if direction.y > 0. {
+            self.scene().lights.add_or_replace(DirectionalLight::new(
+                sun_id,
+                -direction,
+                sun_irradiance(&direction, turbidity, intensity),
+                SUN_ANGULAR_DIAMETER,
+            ).into());
+        } else {
+            self.scene().lights.remove(sun_id);
+        }
+    }
+
+    fn create_or_edit_point_light(&mut self, id: u32, position: Vec3, intensity: Vec3) {
+        self.scene().lights.add_or_replace(PointLight::new(id, position, intensity).into());
+    }
+
+    /// Change the material of a shape. Returns false if there is no such shape.
+    fn set_material(&mut self, id: u32, material: Material) -> bool {
+        if let Some(shape) = self.scene().world.find_mut(id) {
 
             match shape {
-                HitableShape::Sphere(sphere) => {
-                    sphere.material = LambertianMaterial {
-                        albedo: Vec3::new(r as f32 / 255.9, g as f32 / 255.9, b as f32 / 255.9),
-                    }.into();
-                },
-                HitableShape::Triangle(triangle) => {
-                    triangle.material = LambertianMaterial {
-                        albedo: Vec3::new(r as f32 / 255.9, g as f32 / 255.9, b as f32 / 255.9),
-                    }.into();
-                },
+                HitableShape::Sphere(sphere) => sphere.material = material,
+                HitableShape::Triangle(triangle) => triangle.material = material,
+                HitableShape::MeshInstance(instance) => instance.material = Some(material),
             }
 
             true
@@ -275,13 +1000,204 @@ impl Context {
     }
 }
 
-fn extract_triangle(vertices: &Vec<f32>, index: u16) -> Vec3 {
-    let index = index as usize;
-    Vec3::new(
-        vertices[index],
-        vertices[index + 1],
-        vertices[index + 2],
-    )
+/// How textures repeat outside of their [0, 1] coordinates.
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Wrapping {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+impl From<Wrapping> for WrapMode {
+    fn from(wrapping: Wrapping) -> Self {
+        match wrapping {
+            Wrapping::Repeat => WrapMode::Repeat,
+            Wrapping::MirroredRepeat => WrapMode::MirroredRepeat,
+            Wrapping::ClampToEdge => WrapMode::ClampToEdge,
+        }
+    }
+}
+
+/// Camera placement set by the `set_camera_*` calls.
+enum CameraFrame {
+    LookAt { eye: Vec3, target: Vec3, up: Vec3 },
+    Quaternion { position: Vec3, rotation: Quat },
+    Matrices { view: Mat4, projection: Mat4 },
+}
+
+/// How the camera maps the directions to the image.
+/// The fisheye covers the camera field of view on its image circle.
+/// `Perspective` keeps the projection given by `set_camera_matrices`, if any.
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CameraProjection {
+    Perspective,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+}
+
+/// How the light arriving on surfaces is estimated.
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Sampling {
+    /// Only sample the lights.
+    Naive,
+    /// Combine light and material sampling with multiple importance sampling.
+    Mis,
+}
+
+/// How the radiance is brought to the range of the display, before the sRGB encoding.
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ToneMappingOperator {
+    /// Clip the colours above one.
+    Linear,
+    Reinhard,
+    /// Reinhard reaching white at `white_point`.
+    ExtendedReinhard,
+    /// Filmic curve of the Academy Color Encoding System.
+    Aces,
+    /// Filmic curve desaturating the bright colours towards white.
+    Agx,
+}
+
+/// How the samples are weighted around the pixels.
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ReconstructionFilter {
+    /// Each pixel averages its own samples, with the default radius.
+    Box,
+    Tent,
+    Gaussian,
+    /// Mitchell–Netravali, sharper than the others.
+    Mitchell,
+    BlackmanHarris,
+}
+
+impl From<ReconstructionFilter> for Filter {
+    fn from(filter: ReconstructionFilter) -> Self {
+        match filter {
+            ReconstructionFilter::Box => Filter::Box,
+            ReconstructionFilter::Tent => Filter::Tent,
+            ReconstructionFilter::Gaussian => Filter::Gaussian,
+            ReconstructionFilter::Mitchell => Filter::Mitchell,
+            ReconstructionFilter::BlackmanHarris => Filter::BlackmanHarris,
+        }
+    }
+}
+
+/// Auxiliary outputs given as floats.
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RenderPass {
+    /// Distance from the camera to the first surface hit, zero where nothing was hit.
+    Depth,
+    Position,
+    Normal,
+    Albedo,
+    /// Lighting coming straight from the lights or after a single bounce.
+    Direct,
+    Indirect,
+}
+
+/// Auxiliary outputs given as ids.
+#[wasm_bindgen]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum IdPass {
+    /// Id of the shape.
+    Object,
+    /// Id given to the material of the shape with `set_material_id`, 0 by default.
+    Material,
+}
+
+impl From<Sampling> for Integrator {
+    fn from(sampling: Sampling) -> Self {
+        match sampling {
+            Sampling::Naive => Integrator::Naive,
+            Sampling::Mis => Integrator::Mis,
+        }
+    }
+}
+
+/// Tone mapped and sRGB encoded RGBA image of linear colours.
+fn to_rgba8(colors: &[Vec3], tone_mapping: ToneMapping, exposure: f32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(colors.len() * 4);
+    for col in colors {
+        let better_color = tone_mapping.to_srgb(col, exposure);
+        data.push((255.99 * better_color.x) as u8);
+        data.push((255.99 * better_color.y) as u8);
+        data.push((255.99 * better_color.z) as u8);
+        data.push(255);
+    }
+    data
+}
+
+fn to_rgba32(colors: &[Vec3]) -> Vec<f32> {
+    let mut data = Vec::with_capacity(colors.len() * 4);
+    for col in colors {
+        data.extend_from_slice(&[col.x, col.y, col.z, 1.]);
+    }
+    data
+}
+
+fn to_pass(aovs: &[Aovs], pass: RenderPass) -> Vec<f32> {
+    let mut data = Vec::with_capacity(aovs.len() * 3);
+    for aov in aovs {
+        match pass {
+            RenderPass::Depth => data.push(aov.depth),
+            RenderPass::Position => data.extend_from_slice(aov.position.as_slice()),
+            RenderPass::Normal => data.extend_from_slice(aov.normal.as_slice()),
+            RenderPass::Albedo => data.extend_from_slice(aov.albedo.as_slice()),
+            RenderPass::Direct => data.extend_from_slice(aov.direct.as_slice()),
+            RenderPass::Indirect => data.extend_from_slice(aov.indirect.as_slice()),
+        }
+    }
+    data
+}
+
+fn to_id_pass(aovs: &[Aovs], pass: IdPass) -> Vec<u32> {
+    aovs.iter()
+        .map(|aov| match pass {
+            IdPass::Object => aov.object_id,
+            IdPass::Material => aov.material_id,
+        })
+        .collect()
+}
+
+/// Colour sent from javascript as sRGB encoded 8-bit channels, brought back to linear RGB
+/// like sRGB textures and black body colours. The same picked colour then gives the same
+/// albedo as a texture, and the same radiance for a light as the matching temperature.
+fn to_color(r: u32, g: u32, b: u32) -> Vec3 {
+    Vec3::new(r as f32, g as f32, b as f32).map(|channel| srgb_to_linear(clamp(channel, 0., 255.) / 255.))
+}
+
+/// Perlin noise with a fixed seed, so every worker renders the same noise.
+fn seeded_perlin() -> Rc<Perlin> {
+    Rc::new(Perlin::new(0))
+}
+
+/// Build a grey mesh from the buffers sent by javascript.
+fn build_mesh(vertices: &[f32], normals: &[f32], uvs: &[f32], triangles: &[u32]) -> Result<Mesh, JsValue> {
+    Mesh::new(
+        vertices,
+        normals,
+        uvs,
+        triangles,
+        LambertianMaterial {
+            albedo: Vec3::new(0.5, 0.5, 0.5).into(),
+        }.into(),
+    ).map_err(|error| JsValue::from_str(&error.to_string()))
+}
+
+/// Read a column-major 4x4 matrix sent from javascript.
+fn to_mat4(values: &[f32]) -> Option<Mat4> {
+    if values.len() == 16 {
+        Some(Mat4::from_column_slice(values))
+    } else {
+        None
+    }
 }
 
 /// Wraps around the Vec3 struct from nalgebra for wasm-bindgen
@@ -306,9 +1222,9 @@ impl From<Vec3> for Vector3 {
     }
 }
 
-impl Into<Vec3> for Vector3 {
-    fn into(self) -> Vec3 {
-        Vec3::new(self.x, self.y, self.z)
+impl From<Vector3> for Vec3 {
+    fn from(vector: Vector3) -> Vec3 {
+        Vec3::new(vector.x, vector.y, vector.z)
     }
 }
 